@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use monkey_interpreter_rust::{
+    builtins::Builtins, lexer::Lexer, parser::Parser, token::TokenType, Engine, Interpreter,
+};
+
+// 字句解析と構文解析の対象。構文を一通り含むブロックを繰り返して大きな入力を作る
+fn generate_source(blocks: usize) -> String {
+    let mut source = String::new();
+    for i in 0..blocks {
+        source.push_str(&format!(
+            r#"// block {i}
+let value{i} = {i} * (2 + 3) - -4 / 5;
+let add{i} = fn(a, b) {{ if (a < b) {{ return a + b; }} else {{ a - b }} }};
+let items{i} = [value{i}, add{i}(1, 2), "item\t{i}", true, !false];
+let table{i} = {{"key": items{i}[0], {i}: "value", true: len(items{i})}};
+puts(table{i}["key"] == value{i}, rest(items{i}));
+"#,
+            i = i
+        ));
+    }
+    source
+}
+
+const FIBONACCI: &str = "
+let fibonacci = fn(x) {
+  if (x < 2) { return x; }
+  fibonacci(x - 1) + fibonacci(x - 2)
+};
+";
+
+fn bench_lexer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lexer");
+    for blocks in [100, 1000] {
+        let source = generate_source(blocks);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(blocks), &source, |b, source| {
+            b.iter(|| {
+                let mut lexer = Lexer::new(source);
+                let mut count = 0;
+                while lexer.next_token().token_type != TokenType::Eof {
+                    count += 1;
+                }
+                black_box(count)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parser");
+    for blocks in [100, 1000] {
+        let source = generate_source(blocks);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(blocks), &source, |b, source| {
+            b.iter(|| {
+                let mut parser = Parser::new(Lexer::new(source));
+                black_box(
+                    parser
+                        .parse_program()
+                        .expect("generated source should parse"),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_fibonacci(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fibonacci(25)");
+    // 評価器では 1 回の実行に 0.5 秒ほどかかるので、サンプル数を最小にして計測時間を延ばす
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+    for engine in [Engine::Eval, Engine::Vm] {
+        let mut interpreter = Interpreter::with_engine(engine, Builtins::default());
+        interpreter.eval(FIBONACCI).unwrap();
+        group.bench_function(BenchmarkId::from_parameter(engine), |b| {
+            b.iter(|| interpreter.eval(black_box("fibonacci(25)")).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer, bench_parser, bench_fibonacci);
+criterion_main!(benches);
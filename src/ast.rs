@@ -1,31 +1,193 @@
-use std::fmt;
+use std::fmt::{self, Write};
+
+use crate::{token::Span, RED_ZONE, STACK_SEGMENT};
 pub enum Node {
     Statement,
     Expression,
 }
 
-#[derive(PartialEq, Debug)]
-pub enum Expression {
+#[derive(Debug)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+// 複製・解放・表示は子の式を再帰で辿るので、深く入れ子になった式でもスタックが溢れないようにする
+impl Clone for Expression {
+    fn clone(&self) -> Self {
+        let kind = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || self.kind.clone());
+        Self::new(kind, self.span)
+    }
+}
+
+impl Drop for Expression {
+    fn drop(&mut self) {
+        let kind = std::mem::replace(&mut self.kind, ExpressionKind::Boolean(false));
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, move || drop(kind));
+    }
+}
+
+// 位置情報は比較に含めず、構文木の形だけで比較する
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || write!(f, "{}", self.kind))
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ExpressionKind {
     Identifier(String),
     IntegerLiteral(i64),
+    Boolean(bool),
+    StringLiteral(String),
+    Array(Vec<Expression>),
+    Hash(Vec<(Expression, Expression)>),
+    Prefix {
+        operator: String,
+        right: Box<Expression>,
+    },
+    Infix {
+        left: Box<Expression>,
+        operator: String,
+        right: Box<Expression>,
+    },
+    If {
+        condition: Box<Expression>,
+        consequence: Box<Statement>,
+        alternative: Option<Box<Statement>>,
+    },
+    Function {
+        parameters: Vec<String>,
+        body: Box<Statement>,
+    },
+    Call {
+        function: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
+    Index {
+        left: Box<Expression>,
+        index: Box<Expression>,
+    },
 }
 
-impl fmt::Display for Expression {
+impl fmt::Display for ExpressionKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Expression::Identifier(value) => write!(f, "{}", value),
-            Expression::IntegerLiteral(value) => write!(f, "{}", value),
+            ExpressionKind::Identifier(value) => write!(f, "{}", value),
+            ExpressionKind::IntegerLiteral(value) => write!(f, "{}", value),
+            ExpressionKind::Boolean(value) => write!(f, "{}", value),
+            ExpressionKind::StringLiteral(value) => write_string_literal(f, value),
+            ExpressionKind::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            ExpressionKind::Hash(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            ExpressionKind::Prefix { operator, right } => write!(f, "({}{})", operator, right),
+            ExpressionKind::Infix {
+                left,
+                operator,
+                right,
+            } => write!(f, "({} {} {})", left, operator, right),
+            ExpressionKind::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                write!(f, "if {} {}", condition, consequence)?;
+                if let Some(alternative) = alternative {
+                    write!(f, " else {}", alternative)?;
+                }
+                Ok(())
+            }
+            ExpressionKind::Function { parameters, body } => {
+                write!(f, "fn({}) {}", parameters.join(", "), body)
+            }
+            ExpressionKind::Call {
+                function,
+                arguments,
+            } => {
+                let arguments: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", function, arguments.join(", "))
+            }
+            ExpressionKind::Index { left, index } => write!(f, "({}[{}])", left, index),
+        }
+    }
+}
+
+// 字句解析器が受け付けるエスケープだけを使って文字列リテラルを書く。出力はそのまま構文解析し直せる
+fn write_string_literal(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for ch in value.chars() {
+        match ch {
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            ch if ch.is_control() => write!(f, "\\u{{{:x}}}", ch as u32)?,
+            ch => f.write_char(ch)?,
         }
     }
+    f.write_char('"')
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self { kind, span }
+    }
 }
 
-#[derive(PartialEq, Debug)]
-pub enum Statement {
-    Let { name: Expression },
-    Return, // TODO: Return(Expression) にする(戻り値の式を保持する)。2.5 時点ではスキップ
+// 式と同じく、位置情報は比較に含めない
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum StatementKind {
+    Let { name: Expression, value: Expression },
+    Return(Expression),
     Expression(Expression),
+    Block(Vec<Statement>),
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
@@ -33,23 +195,36 @@ pub struct Program {
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for statement in self.statements.iter() {
-            write!(f, "{}\n", statement)?;
+            writeln!(f, "{}", statement)?;
         }
         Ok(())
     }
 }
 
 impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl fmt::Display for StatementKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Statement::Let { name } => write!(f, "let {} = ", name),
-            Statement::Return => write!(f, "return (TODO: 式);"),
-            Statement::Expression(expression) => write!(f, "{}", expression),
+            StatementKind::Let { name, value } => write!(f, "let {} = {};", name, value),
+            StatementKind::Return(value) => write!(f, "return {};", value),
+            StatementKind::Expression(expression) => write!(f, "{}", expression),
+            StatementKind::Block(statements) => {
+                write!(f, "{{ ")?;
+                for statement in statements.iter() {
+                    write!(f, "{} ", statement)?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
 pub enum Precedence {
     Lowest,
     Equals,      // ==
@@ -58,4 +233,5 @@ pub enum Precedence {
     Product,     // *
     Prefix,      // -X or !X
     Call,        // myFunction(X){}
+    Index,       // array[index]
 }
@@ -0,0 +1,282 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{evaluator::RuntimeError, object::Object};
+
+pub type BuiltinFunction = fn(&[Object]) -> Result<Object, RuntimeError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    Variadic,
+}
+
+// 関数ポインタ同士の比較は意味を持たないため、組み込み関数は名前で比較する
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub arity: Arity,
+    pub function: BuiltinFunction,
+}
+
+impl Builtin {
+    pub fn call(&self, arguments: &[Object]) -> Result<Object, RuntimeError> {
+        if let Arity::Exact(want) = self.arity {
+            if arguments.len() != want {
+                return Err(RuntimeError::WrongArgumentCount {
+                    want,
+                    got: arguments.len(),
+                });
+            }
+        }
+        (self.function)(arguments)
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+// 名前から組み込み関数を引く表。識別子が環境に見つからなかったときに参照される
+// バイトコードからは登録順の添字で参照するので、並びは登録順に保つ
+#[derive(Debug, Clone)]
+pub struct Builtins {
+    functions: Vec<Builtin>,
+    indices: HashMap<String, usize>,
+}
+
+impl Builtins {
+    // 標準の組み込み関数を含まない空の表
+    pub fn empty() -> Self {
+        Self {
+            functions: vec![],
+            indices: HashMap::new(),
+        }
+    }
+
+    // 同じ名前が登録済みであれば、添字を変えずに置き換える
+    pub fn register(&mut self, name: &str, arity: Arity, function: BuiltinFunction) {
+        let builtin = Builtin {
+            name: String::from(name),
+            arity,
+            function,
+        };
+        match self.indices.get(name) {
+            Some(&index) => self.functions[index] = builtin,
+            None => {
+                self.indices
+                    .insert(String::from(name), self.functions.len());
+                self.functions.push(builtin);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        let index = *self.indices.get(name)?;
+        self.get_by_index(index)
+    }
+
+    pub fn get_by_index(&self, index: usize) -> Option<Object> {
+        self.functions.get(index).cloned().map(Object::Builtin)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Builtin> {
+        self.functions.iter()
+    }
+}
+
+impl Default for Builtins {
+    fn default() -> Self {
+        let mut builtins = Self::empty();
+        builtins.register("len", Arity::Exact(1), len);
+        builtins.register("first", Arity::Exact(1), first);
+        builtins.register("last", Arity::Exact(1), last);
+        builtins.register("rest", Arity::Exact(1), rest);
+        builtins.register("push", Arity::Exact(2), push);
+        builtins.register("puts", Arity::Variadic, puts);
+        builtins
+    }
+}
+
+pub fn array_argument<'a>(name: &str, argument: &'a Object) -> Result<&'a [Object], RuntimeError> {
+    match argument {
+        Object::Array(elements) => Ok(elements),
+        _ => Err(RuntimeError::ArgumentType {
+            function: String::from(name),
+            expected: "ARRAY",
+            actual: argument.type_name(),
+        }),
+    }
+}
+
+pub fn integer_argument(name: &str, argument: &Object) -> Result<i64, RuntimeError> {
+    match argument {
+        Object::Integer(value) => Ok(*value),
+        _ => Err(RuntimeError::ArgumentType {
+            function: String::from(name),
+            expected: "INTEGER",
+            actual: argument.type_name(),
+        }),
+    }
+}
+
+pub fn string_argument<'a>(name: &str, argument: &'a Object) -> Result<&'a str, RuntimeError> {
+    match argument {
+        Object::String(value) => Ok(value),
+        _ => Err(RuntimeError::ArgumentType {
+            function: String::from(name),
+            expected: "STRING",
+            actual: argument.type_name(),
+        }),
+    }
+}
+
+fn len(arguments: &[Object]) -> Result<Object, RuntimeError> {
+    match &arguments[0] {
+        Object::String(value) => Ok(Object::Integer(value.chars().count() as i64)),
+        Object::Array(elements) => Ok(Object::Integer(elements.len() as i64)),
+        argument => Err(RuntimeError::UnsupportedArgument {
+            function: String::from("len"),
+            actual: argument.type_name(),
+        }),
+    }
+}
+
+fn first(arguments: &[Object]) -> Result<Object, RuntimeError> {
+    let elements = array_argument("first", &arguments[0])?;
+    Ok(elements.first().cloned().unwrap_or(Object::Null))
+}
+
+fn last(arguments: &[Object]) -> Result<Object, RuntimeError> {
+    let elements = array_argument("last", &arguments[0])?;
+    Ok(elements.last().cloned().unwrap_or(Object::Null))
+}
+
+fn rest(arguments: &[Object]) -> Result<Object, RuntimeError> {
+    let elements = array_argument("rest", &arguments[0])?;
+    if elements.is_empty() {
+        return Ok(Object::Null);
+    }
+    Ok(Object::Array(elements[1..].to_vec()))
+}
+
+// 元の配列は変更せず、要素を追加した新しい配列を返す
+fn push(arguments: &[Object]) -> Result<Object, RuntimeError> {
+    let elements = array_argument("push", &arguments[0])?;
+    let mut elements = elements.to_vec();
+    elements.push(arguments[1].clone());
+    Ok(Object::Array(elements))
+}
+
+fn puts(arguments: &[Object]) -> Result<Object, RuntimeError> {
+    for argument in arguments {
+        println!("{}", argument);
+    }
+    Ok(Object::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{integer_argument, string_argument, Arity, Builtins};
+    use crate::{
+        environment::Environment,
+        evaluator::{eval, RuntimeError},
+        object::Object,
+        parse,
+    };
+
+    fn eval_with(builtins: Builtins, input: &str) -> Object {
+        let program = parse(input).expect("failed to parse input");
+        let env = Rc::new(RefCell::new(Environment::with_builtins(builtins)));
+        eval(&program, &env)
+    }
+
+    fn host_builtins() -> Builtins {
+        let mut builtins = Builtins::default();
+        builtins.register("now", Arity::Exact(0), |_| {
+            Ok(Object::Integer(1_600_000_000))
+        });
+        builtins.register("repeat", Arity::Exact(2), |arguments| {
+            let text = string_argument("repeat", &arguments[0])?;
+            let count = integer_argument("repeat", &arguments[1])?;
+            if count < 0 {
+                return Err(RuntimeError::Custom(String::from(
+                    "`repeat` count must not be negative",
+                )));
+            }
+            Ok(Object::String(text.repeat(count as usize)))
+        });
+        builtins.register("sum", Arity::Variadic, |arguments| {
+            let mut total = 0;
+            for argument in arguments {
+                total += integer_argument("sum", argument)?;
+            }
+            Ok(Object::Integer(total))
+        });
+        builtins
+    }
+
+    #[test]
+    fn test_host_functions() {
+        let tests = vec![
+            ("now()", Object::Integer(1_600_000_000)),
+            (r#"repeat("ab", 3)"#, Object::String(String::from("ababab"))),
+            ("sum()", Object::Integer(0)),
+            ("sum(1, 2, 3)", Object::Integer(6)),
+            (
+                "let f = fn() { now() }; f()",
+                Object::Integer(1_600_000_000),
+            ),
+            ("len([1, 2])", Object::Integer(2)),
+            // 環境の束縛は組み込み関数より優先される
+            ("let now = fn() { 1 }; now()", Object::Integer(1)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, eval_with(host_builtins(), input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_host_function_errors() {
+        let tests = vec![
+            ("now(1)", "wrong number of arguments: want=0, got=1"),
+            (
+                "repeat(1, 2)",
+                "argument to `repeat` must be STRING, got INTEGER",
+            ),
+            (
+                r#"sum(1, "2")"#,
+                "argument to `sum` must be INTEGER, got STRING",
+            ),
+            (r#"repeat("a", -1)"#, "`repeat` count must not be negative"),
+        ];
+
+        for (input, expected) in tests {
+            match eval_with(host_builtins(), input) {
+                Object::Error { message, .. } => assert_eq!(expected, message, "{}", input),
+                evaluated => panic!("no error object returned. got {:?}", evaluated),
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_builtins() {
+        match eval_with(Builtins::empty(), "len([])") {
+            Object::Error { message, .. } => assert_eq!("identifier not found: len", message),
+            evaluated => panic!("no error object returned. got {:?}", evaluated),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::{convert::TryFrom, fmt};
+
+use crate::token::Span;
+
+// 命令列。1 バイトのオペコードの後に、定義で決まった幅のオペランドがビッグエンディアンで続く
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Instructions(pub Vec<u8>);
+
+impl Instructions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// 逆アセンブル結果。1 行に 1 命令を `0000 OpConstant 1` の形で出力する
+impl fmt::Display for Instructions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut i = 0;
+        while i < self.0.len() {
+            let op = match Opcode::try_from(self.0[i]) {
+                Ok(op) => op,
+                Err(byte) => {
+                    writeln!(f, "{:04} ERROR: unknown opcode {}", i, byte)?;
+                    i += 1;
+                    continue;
+                }
+            };
+            let definition = op.definition();
+            if i + 1 + definition.operand_widths.iter().sum::<usize>() > self.0.len() {
+                writeln!(f, "{:04} ERROR: truncated {}", i, definition.name)?;
+                break;
+            }
+
+            let (operands, read) = read_operands(&definition, &self.0[i + 1..]);
+            write!(f, "{:04} {}", i, definition.name)?;
+            for operand in operands {
+                write!(f, " {}", operand)?;
+            }
+            writeln!(f)?;
+            i += 1 + read;
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<u8>> for Instructions {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+// 命令の位置と、その命令を生成した式のソース上の位置の対応。位置の昇順に並ぶ
+// 直前と同じ Span の命令は記録しないので、ある命令の Span はそれ以前で最後に記録されたもの
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap(pub Vec<(usize, Span)>);
+
+impl SourceMap {
+    pub fn push(&mut self, position: usize, span: Span) {
+        if self.0.last().map(|(_, last)| *last) != Some(span) {
+            self.0.push((position, span));
+        }
+    }
+
+    // position 以降の命令の記録を取り除く
+    pub fn truncate(&mut self, position: usize) {
+        let len = self.0.partition_point(|(start, _)| *start < position);
+        self.0.truncate(len);
+    }
+
+    // offset のバイトを含む命令の Span
+    pub fn find(&self, offset: usize) -> Option<Span> {
+        let index = self.0.partition_point(|(start, _)| *start <= offset);
+        index.checked_sub(1).map(|index| self.0[index].1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+    Constant,
+    Pop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    True,
+    False,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    Minus,
+    Bang,
+    JumpNotTruthy,
+    Jump,
+    Null,
+    GetGlobal,
+    SetGlobal,
+    Array,
+    Hash,
+    Index,
+    Call,
+    ReturnValue,
+    Return,
+    GetLocal,
+    SetLocal,
+    GetBuiltin,
+    Closure,
+    GetFree,
+    CurrentClosure,
+    GetDeferredGlobal,
+}
+
+pub struct Definition {
+    pub name: &'static str,
+    pub operand_widths: &'static [usize],
+}
+
+impl Opcode {
+    pub fn definition(self) -> Definition {
+        let (name, operand_widths): (&'static str, &'static [usize]) = match self {
+            Opcode::Constant => ("OpConstant", &[2]),
+            Opcode::Pop => ("OpPop", &[]),
+            Opcode::Add => ("OpAdd", &[]),
+            Opcode::Sub => ("OpSub", &[]),
+            Opcode::Mul => ("OpMul", &[]),
+            Opcode::Div => ("OpDiv", &[]),
+            Opcode::True => ("OpTrue", &[]),
+            Opcode::False => ("OpFalse", &[]),
+            Opcode::Equal => ("OpEqual", &[]),
+            Opcode::NotEqual => ("OpNotEqual", &[]),
+            Opcode::GreaterThan => ("OpGreaterThan", &[]),
+            Opcode::LessThan => ("OpLessThan", &[]),
+            Opcode::Minus => ("OpMinus", &[]),
+            Opcode::Bang => ("OpBang", &[]),
+            Opcode::JumpNotTruthy => ("OpJumpNotTruthy", &[2]),
+            Opcode::Jump => ("OpJump", &[2]),
+            Opcode::Null => ("OpNull", &[]),
+            Opcode::GetGlobal => ("OpGetGlobal", &[2]),
+            Opcode::SetGlobal => ("OpSetGlobal", &[2]),
+            Opcode::Array => ("OpArray", &[2]),
+            Opcode::Hash => ("OpHash", &[2]),
+            Opcode::Index => ("OpIndex", &[]),
+            Opcode::Call => ("OpCall", &[1]),
+            Opcode::ReturnValue => ("OpReturnValue", &[]),
+            Opcode::Return => ("OpReturn", &[]),
+            Opcode::GetLocal => ("OpGetLocal", &[1]),
+            Opcode::SetLocal => ("OpSetLocal", &[1]),
+            Opcode::GetBuiltin => ("OpGetBuiltin", &[1]),
+            // 定数プール上の関数の位置と、取り込む自由変数の数
+            Opcode::Closure => ("OpClosure", &[2, 1]),
+            Opcode::GetFree => ("OpGetFree", &[1]),
+            Opcode::CurrentClosure => ("OpCurrentClosure", &[]),
+            // 後から let される大域変数の位置と、未定義のときのエラーに使う名前の定数の位置
+            Opcode::GetDeferredGlobal => ("OpGetDeferredGlobal", &[2, 2]),
+        };
+        Definition {
+            name,
+            operand_widths,
+        }
+    }
+}
+
+// バイト値からオペコードを引くための表。並びは Opcode の宣言順と一致させる
+const OPCODES: [Opcode; 32] = [
+    Opcode::Constant,
+    Opcode::Pop,
+    Opcode::Add,
+    Opcode::Sub,
+    Opcode::Mul,
+    Opcode::Div,
+    Opcode::True,
+    Opcode::False,
+    Opcode::Equal,
+    Opcode::NotEqual,
+    Opcode::GreaterThan,
+    Opcode::LessThan,
+    Opcode::Minus,
+    Opcode::Bang,
+    Opcode::JumpNotTruthy,
+    Opcode::Jump,
+    Opcode::Null,
+    Opcode::GetGlobal,
+    Opcode::SetGlobal,
+    Opcode::Array,
+    Opcode::Hash,
+    Opcode::Index,
+    Opcode::Call,
+    Opcode::ReturnValue,
+    Opcode::Return,
+    Opcode::GetLocal,
+    Opcode::SetLocal,
+    Opcode::GetBuiltin,
+    Opcode::Closure,
+    Opcode::GetFree,
+    Opcode::CurrentClosure,
+    Opcode::GetDeferredGlobal,
+];
+
+impl TryFrom<u8> for Opcode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+pub fn make(op: Opcode, operands: &[usize]) -> Vec<u8> {
+    let definition = op.definition();
+    let mut instruction = vec![op as u8];
+
+    for (operand, width) in operands.iter().zip(definition.operand_widths) {
+        match width {
+            2 => instruction.extend_from_slice(&(*operand as u16).to_be_bytes()),
+            1 => instruction.push(*operand as u8),
+            _ => unreachable!("unsupported operand width {}", width),
+        }
+    }
+
+    instruction
+}
+
+// オペランドを読み、その値と読んだバイト数を返す
+pub fn read_operands(definition: &Definition, instructions: &[u8]) -> (Vec<usize>, usize) {
+    let mut operands = Vec::with_capacity(definition.operand_widths.len());
+    let mut offset = 0;
+
+    for width in definition.operand_widths {
+        match width {
+            2 => operands.push(read_u16(&instructions[offset..]) as usize),
+            1 => operands.push(instructions[offset] as usize),
+            _ => unreachable!("unsupported operand width {}", width),
+        }
+        offset += width;
+    }
+
+    (operands, offset)
+}
+
+pub fn read_u16(instructions: &[u8]) -> u16 {
+    u16::from_be_bytes([instructions[0], instructions[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{make, read_operands, Instructions, Opcode, SourceMap, OPCODES};
+    use crate::token::Span;
+
+    #[test]
+    fn test_make() {
+        let tests = vec![
+            (
+                Opcode::Constant,
+                vec![65534],
+                vec![Opcode::Constant as u8, 255, 254],
+            ),
+            (Opcode::Add, vec![], vec![Opcode::Add as u8]),
+            (
+                Opcode::GetLocal,
+                vec![255],
+                vec![Opcode::GetLocal as u8, 255],
+            ),
+            (
+                Opcode::Closure,
+                vec![65534, 255],
+                vec![Opcode::Closure as u8, 255, 254, 255],
+            ),
+        ];
+
+        for (op, operands, expected) in tests {
+            assert_eq!(expected, make(op, &operands));
+        }
+    }
+
+    #[test]
+    fn test_instructions_string() {
+        let instructions = Instructions(
+            [
+                make(Opcode::Add, &[]),
+                make(Opcode::GetLocal, &[1]),
+                make(Opcode::Constant, &[2]),
+                make(Opcode::Constant, &[65535]),
+                make(Opcode::Closure, &[65535, 255]),
+            ]
+            .concat(),
+        );
+
+        let expected = "\
+0000 OpAdd
+0001 OpGetLocal 1
+0003 OpConstant 2
+0006 OpConstant 65535
+0009 OpClosure 65535 255
+";
+        assert_eq!(expected, instructions.to_string());
+    }
+
+    #[test]
+    fn test_malformed_instructions_string() {
+        let instructions = Instructions(vec![200, Opcode::Pop as u8, Opcode::Constant as u8, 0]);
+
+        let expected = "\
+0000 ERROR: unknown opcode 200
+0001 OpPop
+0002 ERROR: truncated OpConstant
+";
+        assert_eq!(expected, instructions.to_string());
+    }
+
+    #[test]
+    fn test_read_operands() {
+        let tests = vec![
+            (Opcode::Constant, vec![65535], 2),
+            (Opcode::GetLocal, vec![255], 1),
+            (Opcode::Closure, vec![65535, 255], 3),
+        ];
+
+        for (op, operands, bytes_read) in tests {
+            let instruction = make(op, &operands);
+            let definition = op.definition();
+
+            let (operands_read, n) = read_operands(&definition, &instruction[1..]);
+            assert_eq!(bytes_read, n);
+            assert_eq!(operands, operands_read);
+        }
+    }
+
+    #[test]
+    fn test_opcode_from_byte() {
+        assert_eq!(Ok(Opcode::Constant), Opcode::try_from(0));
+        assert_eq!(
+            Ok(Opcode::CurrentClosure),
+            Opcode::try_from(Opcode::CurrentClosure as u8)
+        );
+        assert_eq!(Err(200), Opcode::try_from(200));
+
+        for (i, op) in OPCODES.iter().enumerate() {
+            assert_eq!(i as u8, *op as u8);
+        }
+    }
+
+    #[test]
+    fn test_source_map() {
+        let span = |line| Span {
+            start: 0,
+            end: 1,
+            line,
+            column: 1,
+        };
+        let mut source_map = SourceMap::default();
+        source_map.push(0, span(1));
+        source_map.push(3, span(1));
+        source_map.push(4, span(2));
+        source_map.push(7, span(3));
+        assert_eq!(3, source_map.0.len());
+
+        assert_eq!(Some(span(1)), source_map.find(3));
+        assert_eq!(Some(span(2)), source_map.find(6));
+        assert_eq!(Some(span(3)), source_map.find(100));
+
+        source_map.truncate(7);
+        assert_eq!(Some(span(2)), source_map.find(7));
+        assert_eq!(None, SourceMap::default().find(0));
+    }
+}
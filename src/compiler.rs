@@ -0,0 +1,1152 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fmt, mem,
+    rc::Rc,
+};
+
+use thiserror::Error;
+
+use crate::{
+    ast::{Expression, ExpressionKind, Program, Statement, StatementKind},
+    builtins::Builtins,
+    code::{make, Instructions, Opcode, SourceMap},
+    object::{CompiledFunction, HashKey, Hashable, Object},
+    symbol_table::{self, Symbol, SymbolScope, SymbolTable},
+    token::Span,
+    RED_ZONE, STACK_SEGMENT,
+};
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CompileError {
+    #[error("unknown operator: {operator}")]
+    UnknownOperator { operator: String, span: Span },
+    // 定数・ローカル変数・引数などが多すぎて、命令のオペランドに収まらない
+    #[error("operand {operand} of {opcode} is out of range (max {max})")]
+    OperandOverflow {
+        opcode: &'static str,
+        operand: usize,
+        max: usize,
+        span: Span,
+    },
+}
+
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::UnknownOperator { span, .. } => *span,
+            CompileError::OperandOverflow { span, .. } => *span,
+        }
+    }
+}
+
+// VM に渡す命令列と定数プール。定数プールはコンパイラと共有するので、eval のたびに複製しない
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub instructions: Instructions,
+    pub constants: Rc<Vec<Object>>,
+    // 実行時エラーの位置に使う。.mkc から読み込んだ場合は空
+    pub source_map: SourceMap,
+}
+
+// 最上位の命令列に続けて、定数プール上の関数の命令列を出力する
+impl fmt::Display for Bytecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "== main ==")?;
+        write!(f, "{}", self.instructions)?;
+
+        for (index, constant) in self.constants.iter().enumerate() {
+            match constant {
+                Object::CompiledFunction(function) => {
+                    writeln!(
+                        f,
+                        "\n== constant {}: fn (parameters: {}, locals: {}) ==",
+                        index, function.num_parameters, function.num_locals
+                    )?;
+                    write!(f, "{}", function.instructions)?;
+                }
+                Object::String(value) => writeln!(f, "\n== constant {}: {:?} ==", index, value)?,
+                constant => writeln!(f, "\n== constant {}: {} ==", index, constant)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmittedInstruction {
+    opcode: Opcode,
+    position: usize,
+}
+
+// 関数ごとの命令列。関数リテラルに入るたびに積み、抜けるときに降ろす
+#[derive(Debug, Default)]
+struct CompilationScope {
+    instructions: Vec<u8>,
+    source_map: SourceMap,
+    last_instruction: Option<EmittedInstruction>,
+}
+
+#[derive(Debug)]
+pub struct Compiler {
+    constants: Rc<Vec<Object>>,
+    // 整数と文字列の定数の、定数プール上の位置
+    constant_indices: HashMap<HashKey, usize>,
+    symbol_table: SymbolTable,
+    // 関数の本体から参照されたが、まだ最上位で let されていない名前
+    undefined_globals: HashSet<String>,
+    scopes: Vec<CompilationScope>,
+    // コンパイル中の式の位置。エラーの位置に使う
+    span: Span,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::with_builtins(&Builtins::default())
+    }
+
+    // 組み込み関数は登録順の添字で参照するので、VM にも同じ Builtins を渡す
+    pub fn with_builtins(builtins: &Builtins) -> Self {
+        let mut symbol_table = SymbolTable::new();
+        for (index, builtin) in builtins.iter().enumerate() {
+            symbol_table.define_builtin(index, &builtin.name);
+        }
+
+        Self {
+            constants: Rc::default(),
+            constant_indices: HashMap::new(),
+            symbol_table,
+            undefined_globals: HashSet::new(),
+            scopes: vec![CompilationScope::default()],
+            span: Span::default(),
+        }
+    }
+
+    // 失敗した場合は、シンボルテーブルと定数プールを compile を呼ぶ前の状態に戻す
+    pub fn compile(&mut self, program: &Program) -> Result<(), CompileError> {
+        let symbol_table = self.symbol_table.clone();
+        let undefined_globals = self.undefined_globals.clone();
+        let num_constants = self.constants.len();
+        let num_instructions = self.scope().instructions.len();
+        let last_instruction = self.scope().last_instruction;
+
+        let result = program
+            .statements
+            .iter()
+            .try_for_each(|statement| self.compile_statement(statement));
+
+        if result.is_err() {
+            self.scopes.truncate(1);
+            self.symbol_table = symbol_table;
+            self.undefined_globals = undefined_globals;
+            if self.constants.len() > num_constants {
+                Rc::make_mut(&mut self.constants).truncate(num_constants);
+            }
+            self.constant_indices
+                .retain(|_, index| *index < num_constants);
+            let scope = self.scope_mut();
+            scope.instructions.truncate(num_instructions);
+            scope.source_map.truncate(num_instructions);
+            scope.last_instruction = last_instruction;
+        }
+        result
+    }
+
+    pub fn bytecode(&self) -> Bytecode {
+        Bytecode {
+            instructions: Instructions(self.scope().instructions.clone()),
+            constants: self.constants.clone(),
+            source_map: self.scope().source_map.clone(),
+        }
+    }
+
+    // 最上位の命令列を取り出して空にする。シンボルテーブルと定数プールは次の compile に引き継ぐ
+    pub fn take_bytecode(&mut self) -> Bytecode {
+        let scope = mem::take(self.scope_mut());
+        Bytecode {
+            instructions: Instructions(scope.instructions),
+            constants: self.constants.clone(),
+            source_map: scope.source_map,
+        }
+    }
+
+    pub fn define_global(&mut self, name: &str) -> usize {
+        self.undefined_globals.remove(name);
+        self.symbol_table.define(name).index
+    }
+
+    pub fn global_index(&self, name: &str) -> Option<usize> {
+        match self.symbol_table.get(name) {
+            Some(symbol) if symbol.scope == SymbolScope::Global => Some(symbol.index),
+            _ => None,
+        }
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        let outer = mem::replace(&mut self.span, statement.span);
+        let result = self.compile_statement_kind(&statement.kind);
+        self.span = outer;
+        result
+    }
+
+    fn compile_statement_kind(&mut self, statement: &StatementKind) -> Result<(), CompileError> {
+        match statement {
+            StatementKind::Expression(expression) => {
+                self.compile_expression(expression)?;
+                self.emit(Opcode::Pop, &[])?;
+            }
+            StatementKind::Let { name, value } => {
+                let name = match &name.kind {
+                    ExpressionKind::Identifier(name) => name,
+                    _ => unreachable!("let binding name must be an identifier"),
+                };
+                // 値を先にコンパイルするので、`let x = x + 1` の右辺は外側の x を参照する
+                match &value.kind {
+                    ExpressionKind::Function { parameters, body } => {
+                        self.compile_function(parameters, body, Some(name))?
+                    }
+                    _ => self.compile_expression(value)?,
+                }
+                let symbol = self.symbol_table.define(name);
+                match symbol.scope {
+                    SymbolScope::Global => {
+                        self.undefined_globals.remove(name);
+                        self.emit(Opcode::SetGlobal, &[symbol.index])?
+                    }
+                    _ => self.emit(Opcode::SetLocal, &[symbol.index])?,
+                };
+            }
+            StatementKind::Return(value) => {
+                self.compile_expression(value)?;
+                self.emit(Opcode::ReturnValue, &[])?;
+            }
+            StatementKind::Block(statements) => {
+                for statement in statements.iter() {
+                    self.compile_statement(statement)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 子の式をコンパイルし終えると self.span は親の式の位置に戻る
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), CompileError> {
+        let outer = mem::replace(&mut self.span, expression.span);
+        // 深く入れ子になった式でもスタックが溢れないようにする
+        let result = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+            self.compile_expression_kind(expression)
+        });
+        self.span = outer;
+        result
+    }
+
+    fn compile_expression_kind(&mut self, expression: &Expression) -> Result<(), CompileError> {
+        match &expression.kind {
+            ExpressionKind::IntegerLiteral(value) => {
+                let index = self.add_constant(Object::Integer(*value));
+                self.emit(Opcode::Constant, &[index])?;
+            }
+            ExpressionKind::StringLiteral(value) => {
+                let index = self.add_constant(Object::String(value.clone()));
+                self.emit(Opcode::Constant, &[index])?;
+            }
+            ExpressionKind::Boolean(true) => {
+                self.emit(Opcode::True, &[])?;
+            }
+            ExpressionKind::Boolean(false) => {
+                self.emit(Opcode::False, &[])?;
+            }
+            ExpressionKind::Identifier(name) => {
+                // 評価器と同じく、未定義の名前は評価されたときにエラーにする。関数の本体からは
+                // 後から最上位で let される名前も参照でき、実行されない分岐の中ではエラーにならない
+                let symbol = match self.symbol_table.resolve(name) {
+                    Some(symbol) => symbol,
+                    None => {
+                        self.undefined_globals.insert(name.clone());
+                        self.symbol_table.declare_global(name)
+                    }
+                };
+                self.load_symbol(&symbol)?;
+            }
+            ExpressionKind::Array(elements) => {
+                for element in elements.iter() {
+                    self.compile_expression(element)?;
+                }
+                self.emit(Opcode::Array, &[elements.len()])?;
+            }
+            ExpressionKind::Hash(pairs) => {
+                for (key, value) in pairs.iter() {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.emit(Opcode::Hash, &[pairs.len() * 2])?;
+            }
+            ExpressionKind::Index { left, index } => {
+                self.compile_expression(left)?;
+                self.compile_expression(index)?;
+                self.emit(Opcode::Index, &[])?;
+            }
+            ExpressionKind::Prefix { operator, right } => {
+                self.compile_expression(right)?;
+                let op = match operator.as_str() {
+                    "!" => Opcode::Bang,
+                    "-" => Opcode::Minus,
+                    _ => return Err(unknown_operator(operator, expression.span)),
+                };
+                self.emit(op, &[])?;
+            }
+            ExpressionKind::Infix {
+                left,
+                operator,
+                right,
+            } => {
+                self.compile_expression(left)?;
+                self.compile_expression(right)?;
+                let op = match operator.as_str() {
+                    "+" => Opcode::Add,
+                    "-" => Opcode::Sub,
+                    "*" => Opcode::Mul,
+                    "/" => Opcode::Div,
+                    ">" => Opcode::GreaterThan,
+                    "<" => Opcode::LessThan,
+                    "==" => Opcode::Equal,
+                    "!=" => Opcode::NotEqual,
+                    _ => return Err(unknown_operator(operator, expression.span)),
+                };
+                self.emit(op, &[])?;
+            }
+            ExpressionKind::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.compile_expression(condition)?;
+                // 飛び先はブロックをコンパイルした後に書き換える
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[9999])?;
+                self.compile_block_value(consequence)?;
+                let jump = self.emit(Opcode::Jump, &[9999])?;
+
+                let after_consequence = self.scope().instructions.len();
+                self.change_operand(jump_not_truthy, after_consequence)?;
+
+                match alternative {
+                    Some(alternative) => self.compile_block_value(alternative)?,
+                    None => {
+                        self.emit(Opcode::Null, &[])?;
+                    }
+                }
+
+                let after_alternative = self.scope().instructions.len();
+                self.change_operand(jump, after_alternative)?;
+            }
+            ExpressionKind::Function { parameters, body } => {
+                self.compile_function(parameters, body, None)?
+            }
+            ExpressionKind::Call {
+                function,
+                arguments,
+            } => {
+                self.compile_expression(function)?;
+                for argument in arguments.iter() {
+                    self.compile_expression(argument)?;
+                }
+                self.emit(Opcode::Call, &[arguments.len()])?;
+            }
+        }
+        Ok(())
+    }
+
+    // if のブロックは値を 1 つ残す。最後が式文でなければ null を残す
+    fn compile_block_value(&mut self, block: &Statement) -> Result<(), CompileError> {
+        self.compile_statement(block)?;
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_instruction();
+        } else {
+            self.emit(Opcode::Null, &[])?;
+        }
+        Ok(())
+    }
+
+    fn compile_function(
+        &mut self,
+        parameters: &[String],
+        body: &Statement,
+        name: Option<&str>,
+    ) -> Result<(), CompileError> {
+        self.enter_scope();
+
+        if let Some(name) = name {
+            self.symbol_table.define_function_name(name);
+        }
+        for parameter in parameters.iter() {
+            self.symbol_table.define(parameter);
+        }
+
+        self.compile_statement(body)?;
+        // 最後の式の値を暗黙の戻り値にする
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_instruction();
+            self.emit(Opcode::ReturnValue, &[])?;
+        }
+        if !self.last_instruction_is(Opcode::ReturnValue) {
+            self.emit(Opcode::Return, &[])?;
+        }
+
+        let (instructions, source_map, symbol_table) = self.leave_scope();
+        for symbol in symbol_table.free_symbols.iter() {
+            self.load_symbol(symbol)?;
+        }
+
+        let function = CompiledFunction {
+            instructions,
+            num_locals: symbol_table.num_definitions(),
+            num_parameters: parameters.len(),
+            source_map,
+        };
+        let index = self.add_constant(Object::CompiledFunction(Rc::new(function)));
+        self.emit(Opcode::Closure, &[index, symbol_table.free_symbols.len()])?;
+        Ok(())
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol) -> Result<(), CompileError> {
+        match symbol.scope {
+            // 実行時にまだ値が無ければ、評価器と同じく identifier not found とする
+            SymbolScope::Global if self.undefined_globals.contains(&symbol.name) => {
+                let name = self.add_constant(Object::String(symbol.name.clone()));
+                self.emit(Opcode::GetDeferredGlobal, &[symbol.index, name])?
+            }
+            SymbolScope::Global => self.emit(Opcode::GetGlobal, &[symbol.index])?,
+            SymbolScope::Local => self.emit(Opcode::GetLocal, &[symbol.index])?,
+            SymbolScope::Builtin => self.emit(Opcode::GetBuiltin, &[symbol.index])?,
+            SymbolScope::Free => self.emit(Opcode::GetFree, &[symbol.index])?,
+            SymbolScope::Function => self.emit(Opcode::CurrentClosure, &[])?,
+        };
+        Ok(())
+    }
+
+    // 同じ値の整数と文字列は 1 つの定数にまとめる
+    fn add_constant(&mut self, object: Object) -> usize {
+        let key = match object {
+            Object::Integer(_) | Object::String(_) => object.hash_key(),
+            _ => None,
+        };
+        if let Some(index) = key.as_ref().and_then(|key| self.constant_indices.get(key)) {
+            return *index;
+        }
+
+        // 前回の Bytecode を VM が手放していれば、ここで複製は起きない
+        let constants = Rc::make_mut(&mut self.constants);
+        constants.push(object);
+        let index = constants.len() - 1;
+        if let Some(key) = key {
+            self.constant_indices.insert(key, index);
+        }
+        index
+    }
+
+    // 命令を追加し、その位置を返す
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> Result<usize, CompileError> {
+        self.check_operands(op, operands)?;
+        let span = self.span;
+        let instruction = make(op, operands);
+        let scope = self.scope_mut();
+        let position = scope.instructions.len();
+        scope.instructions.extend(instruction);
+        scope.source_map.push(position, span);
+        scope.last_instruction = Some(EmittedInstruction {
+            opcode: op,
+            position,
+        });
+        Ok(position)
+    }
+
+    // make はオペランドを幅に合わせて切り詰めるので、収まらない値は先にエラーにする
+    fn check_operands(&self, op: Opcode, operands: &[usize]) -> Result<(), CompileError> {
+        let definition = op.definition();
+        for (operand, width) in operands.iter().zip(definition.operand_widths) {
+            let max = (1usize << (8 * width)) - 1;
+            if *operand > max {
+                return Err(CompileError::OperandOverflow {
+                    opcode: definition.name,
+                    operand: *operand,
+                    max,
+                    span: self.span,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn last_instruction_is(&self, op: Opcode) -> bool {
+        matches!(self.scope().last_instruction, Some(last) if last.opcode == op)
+    }
+
+    // 直前の命令だけを取り除く。それより前の命令は last_instruction として追跡しない
+    fn remove_last_instruction(&mut self) {
+        let scope = self.scope_mut();
+        if let Some(last) = scope.last_instruction.take() {
+            scope.instructions.truncate(last.position);
+            scope.source_map.truncate(last.position);
+        }
+    }
+
+    fn change_operand(&mut self, position: usize, operand: usize) -> Result<(), CompileError> {
+        let op = Opcode::try_from(self.scope().instructions[position]).expect("invalid opcode");
+        self.check_operands(op, &[operand])?;
+        let instruction = make(op, &[operand]);
+        let scope = self.scope_mut();
+        scope.instructions[position..position + instruction.len()].copy_from_slice(&instruction);
+        Ok(())
+    }
+
+    fn enter_scope(&mut self) {
+        self.scopes.push(CompilationScope::default());
+        symbol_table::enter(&mut self.symbol_table);
+    }
+
+    fn leave_scope(&mut self) -> (Instructions, SourceMap, SymbolTable) {
+        let scope = self.scopes.pop().expect("no compilation scope to leave");
+        let symbol_table = symbol_table::leave(&mut self.symbol_table);
+        (
+            Instructions(scope.instructions),
+            scope.source_map,
+            symbol_table,
+        )
+    }
+
+    fn scope(&self) -> &CompilationScope {
+        self.scopes.last().expect("no compilation scope")
+    }
+
+    fn scope_mut(&mut self) -> &mut CompilationScope {
+        self.scopes.last_mut().expect("no compilation scope")
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn unknown_operator(operator: &str, span: Span) -> CompileError {
+    CompileError::UnknownOperator {
+        operator: String::from(operator),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{CompileError, Compiler};
+    use crate::{
+        code::{make, Instructions, Opcode, SourceMap},
+        object::{CompiledFunction, Object},
+        parse,
+    };
+
+    fn concat(instructions: Vec<Vec<u8>>) -> Instructions {
+        Instructions(instructions.into_iter().flatten().collect())
+    }
+
+    fn function(instructions: Vec<Vec<u8>>, num_locals: usize, num_parameters: usize) -> Object {
+        Object::CompiledFunction(Rc::new(CompiledFunction {
+            instructions: concat(instructions),
+            num_locals,
+            num_parameters,
+            source_map: SourceMap::default(),
+        }))
+    }
+
+    // 期待する定数プールには位置の対応を書かないので、比べる前に取り除く
+    fn without_source_maps(constants: &[Object]) -> Vec<Object> {
+        constants
+            .iter()
+            .map(|constant| match constant {
+                Object::CompiledFunction(function) => {
+                    Object::CompiledFunction(Rc::new(CompiledFunction {
+                        source_map: SourceMap::default(),
+                        ..CompiledFunction::clone(function)
+                    }))
+                }
+                constant => constant.clone(),
+            })
+            .collect()
+    }
+
+    // 入力、期待する定数プール、期待する命令列
+    type CompilerTest<'a> = (&'a str, Vec<Object>, Vec<Vec<u8>>);
+
+    fn run_compiler_tests(tests: Vec<CompilerTest>) {
+        for (input, constants, instructions) in tests {
+            let program = parse(input).expect("failed to parse input");
+            let mut compiler = Compiler::new();
+            compiler.compile(&program).expect("failed to compile");
+
+            let bytecode = compiler.bytecode();
+            assert_eq!(concat(instructions), bytecode.instructions, "{}", input);
+            assert_eq!(
+                constants,
+                without_source_maps(&bytecode.constants),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        run_compiler_tests(vec![
+            (
+                "1 + 2",
+                vec![Object::Integer(1), Object::Integer(2)],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Add, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "1; 2",
+                vec![Object::Integer(1), Object::Integer(2)],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Pop, &[]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "-1 * 2",
+                vec![Object::Integer(1), Object::Integer(2)],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Minus, &[]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Mul, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        run_compiler_tests(vec![
+            (
+                "1 < 2",
+                vec![Object::Integer(1), Object::Integer(2)],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::LessThan, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "!(true == false)",
+                vec![],
+                vec![
+                    make(Opcode::True, &[]),
+                    make(Opcode::False, &[]),
+                    make(Opcode::Equal, &[]),
+                    make(Opcode::Bang, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_conditionals() {
+        run_compiler_tests(vec![
+            (
+                "if (true) { 10 }; 3333;",
+                vec![Object::Integer(10), Object::Integer(3333)],
+                vec![
+                    // 0000
+                    make(Opcode::True, &[]),
+                    // 0001
+                    make(Opcode::JumpNotTruthy, &[10]),
+                    // 0004
+                    make(Opcode::Constant, &[0]),
+                    // 0007
+                    make(Opcode::Jump, &[11]),
+                    // 0010
+                    make(Opcode::Null, &[]),
+                    // 0011
+                    make(Opcode::Pop, &[]),
+                    // 0012
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "if (true) { 10 } else { 20 }",
+                vec![Object::Integer(10), Object::Integer(20)],
+                vec![
+                    make(Opcode::True, &[]),
+                    make(Opcode::JumpNotTruthy, &[10]),
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Jump, &[13]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "if (true) { let x = 1; }",
+                vec![Object::Integer(1)],
+                vec![
+                    make(Opcode::True, &[]),
+                    make(Opcode::JumpNotTruthy, &[14]),
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::SetGlobal, &[0]),
+                    make(Opcode::Null, &[]),
+                    make(Opcode::Jump, &[15]),
+                    make(Opcode::Null, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_global_let_statements() {
+        run_compiler_tests(vec![(
+            "let one = 1; let two = one; two;",
+            vec![Object::Integer(1)],
+            vec![
+                make(Opcode::Constant, &[0]),
+                make(Opcode::SetGlobal, &[0]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::SetGlobal, &[1]),
+                make(Opcode::GetGlobal, &[1]),
+                make(Opcode::Pop, &[]),
+            ],
+        )]);
+    }
+
+    #[test]
+    fn test_collections() {
+        run_compiler_tests(vec![
+            (
+                r#""mon" + "key""#,
+                vec![
+                    Object::String(String::from("mon")),
+                    Object::String(String::from("key")),
+                ],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Add, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "[1, 2][0]",
+                vec![Object::Integer(1), Object::Integer(2), Object::Integer(0)],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Array, &[2]),
+                    make(Opcode::Constant, &[2]),
+                    make(Opcode::Index, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "{1: 2}",
+                vec![Object::Integer(1), Object::Integer(2)],
+                vec![
+                    make(Opcode::Constant, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Hash, &[2]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_functions() {
+        run_compiler_tests(vec![
+            (
+                "fn() { return 5 + 10 }",
+                vec![
+                    Object::Integer(5),
+                    Object::Integer(10),
+                    function(
+                        vec![
+                            make(Opcode::Constant, &[0]),
+                            make(Opcode::Constant, &[1]),
+                            make(Opcode::Add, &[]),
+                            make(Opcode::ReturnValue, &[]),
+                        ],
+                        0,
+                        0,
+                    ),
+                ],
+                vec![make(Opcode::Closure, &[2, 0]), make(Opcode::Pop, &[])],
+            ),
+            (
+                "fn() { 1; 2 }",
+                vec![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    function(
+                        vec![
+                            make(Opcode::Constant, &[0]),
+                            make(Opcode::Pop, &[]),
+                            make(Opcode::Constant, &[1]),
+                            make(Opcode::ReturnValue, &[]),
+                        ],
+                        0,
+                        0,
+                    ),
+                ],
+                vec![make(Opcode::Closure, &[2, 0]), make(Opcode::Pop, &[])],
+            ),
+            (
+                "fn() { }",
+                vec![function(vec![make(Opcode::Return, &[])], 0, 0)],
+                vec![make(Opcode::Closure, &[0, 0]), make(Opcode::Pop, &[])],
+            ),
+            (
+                "let f = fn(a, b) { let c = a; c + b }; f(1, 2);",
+                vec![
+                    function(
+                        vec![
+                            make(Opcode::GetLocal, &[0]),
+                            make(Opcode::SetLocal, &[2]),
+                            make(Opcode::GetLocal, &[2]),
+                            make(Opcode::GetLocal, &[1]),
+                            make(Opcode::Add, &[]),
+                            make(Opcode::ReturnValue, &[]),
+                        ],
+                        3,
+                        2,
+                    ),
+                    Object::Integer(1),
+                    Object::Integer(2),
+                ],
+                vec![
+                    make(Opcode::Closure, &[0, 0]),
+                    make(Opcode::SetGlobal, &[0]),
+                    make(Opcode::GetGlobal, &[0]),
+                    make(Opcode::Constant, &[1]),
+                    make(Opcode::Constant, &[2]),
+                    make(Opcode::Call, &[2]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_builtins() {
+        run_compiler_tests(vec![(
+            "len([]); fn() { push([], 1) };",
+            vec![
+                Object::Integer(1),
+                function(
+                    vec![
+                        make(Opcode::GetBuiltin, &[4]),
+                        make(Opcode::Array, &[0]),
+                        make(Opcode::Constant, &[0]),
+                        make(Opcode::Call, &[2]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    0,
+                    0,
+                ),
+            ],
+            vec![
+                make(Opcode::GetBuiltin, &[0]),
+                make(Opcode::Array, &[0]),
+                make(Opcode::Call, &[1]),
+                make(Opcode::Pop, &[]),
+                make(Opcode::Closure, &[1, 0]),
+                make(Opcode::Pop, &[]),
+            ],
+        )]);
+    }
+
+    #[test]
+    fn test_closures() {
+        run_compiler_tests(vec![(
+            "fn(a) { fn(b) { fn(c) { a + b + c } } };",
+            vec![
+                function(
+                    vec![
+                        make(Opcode::GetFree, &[0]),
+                        make(Opcode::GetFree, &[1]),
+                        make(Opcode::Add, &[]),
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::Add, &[]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    1,
+                ),
+                function(
+                    vec![
+                        make(Opcode::GetFree, &[0]),
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::Closure, &[0, 2]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    1,
+                ),
+                function(
+                    vec![
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::Closure, &[1, 1]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    1,
+                ),
+            ],
+            vec![make(Opcode::Closure, &[2, 0]), make(Opcode::Pop, &[])],
+        )]);
+    }
+
+    #[test]
+    fn test_recursive_functions() {
+        run_compiler_tests(vec![(
+            "let wrapper = fn() { let countDown = fn(x) { countDown(x - 1); }; countDown(1); };",
+            vec![
+                Object::Integer(1),
+                function(
+                    vec![
+                        make(Opcode::CurrentClosure, &[]),
+                        make(Opcode::GetLocal, &[0]),
+                        make(Opcode::Constant, &[0]),
+                        make(Opcode::Sub, &[]),
+                        make(Opcode::Call, &[1]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    1,
+                ),
+                function(
+                    vec![
+                        make(Opcode::Closure, &[1, 0]),
+                        make(Opcode::SetLocal, &[0]),
+                        make(Opcode::GetLocal, &[0]),
+                        // 同じ値の定数は使い回す
+                        make(Opcode::Constant, &[0]),
+                        make(Opcode::Call, &[1]),
+                        make(Opcode::ReturnValue, &[]),
+                    ],
+                    1,
+                    0,
+                ),
+            ],
+            vec![
+                make(Opcode::Closure, &[2, 0]),
+                make(Opcode::SetGlobal, &[0]),
+            ],
+        )]);
+    }
+
+    #[test]
+    fn test_disassemble_bytecode() {
+        let program = parse(r#"let greet = fn(name) { "hi " + name }; greet("x")"#).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).unwrap();
+
+        let expected = "\
+== main ==
+0000 OpClosure 1 0
+0004 OpSetGlobal 0
+0007 OpGetGlobal 0
+0010 OpConstant 2
+0013 OpCall 1
+0015 OpPop
+
+== constant 0: \"hi \" ==
+
+== constant 1: fn (parameters: 1, locals: 1) ==
+0000 OpConstant 0
+0003 OpGetLocal 0
+0005 OpAdd
+0006 OpReturnValue
+
+== constant 2: \"x\" ==
+";
+        assert_eq!(expected, compiler.bytecode().to_string());
+    }
+
+    #[test]
+    fn test_operand_overflow() {
+        // 定数プールの添字は 2 バイトなので、65536 個目の定数は参照できない
+        let input: Vec<String> = (0..=65536).map(|i| i.to_string()).collect();
+        let program = parse(&input.join(";\n")).unwrap();
+        match Compiler::new().compile(&program) {
+            Err(error @ CompileError::OperandOverflow { .. }) => {
+                assert_eq!(
+                    "operand 65536 of OpConstant is out of range (max 65535)",
+                    error.to_string()
+                );
+                assert_eq!(65537, error.span().line);
+            }
+            result => panic!("expected operand overflow. got {:?}", result),
+        }
+
+        // 大域変数への代入で溢れた場合は、let 文全体を指す
+        let input: Vec<String> = (0..=65536).map(|i| format!("let a{} = true;", i)).collect();
+        let input = input.join("\n");
+        let program = parse(&input).unwrap();
+        match Compiler::new().compile(&program) {
+            Err(error @ CompileError::OperandOverflow { .. }) => {
+                assert_eq!(
+                    "operand 65536 of OpSetGlobal is out of range (max 65535)",
+                    error.to_string()
+                );
+                let span = error.span();
+                assert_eq!("let a65536 = true", &input[span.start..span.end]);
+            }
+            result => panic!("expected operand overflow. got {:?}", result),
+        }
+
+        // 引数の数は 1 バイト
+        let arguments = vec!["1"; 256].join(", ");
+        let program = parse(&format!("len({})", arguments)).unwrap();
+        match Compiler::new().compile(&program) {
+            Err(error @ CompileError::OperandOverflow { .. }) => assert_eq!(
+                "operand 256 of OpCall is out of range (max 255)",
+                error.to_string()
+            ),
+            result => panic!("expected operand overflow. got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_shared_constants() {
+        let mut compiler = Compiler::new();
+        compiler.compile(&parse(r#"1; "a""#).unwrap()).unwrap();
+        let first = compiler.take_bytecode();
+        let pool = Rc::as_ptr(&first.constants);
+        drop(first);
+
+        compiler
+            .compile(&parse(r#"1 + 1; "a"; 2"#).unwrap())
+            .unwrap();
+        let second = compiler.take_bytecode();
+        assert_eq!(
+            vec![
+                Object::Integer(1),
+                Object::String(String::from("a")),
+                Object::Integer(2)
+            ],
+            *second.constants
+        );
+        // 前回の Bytecode が手放されていれば、定数プールは複製されずに使い回される
+        assert_eq!(pool, Rc::as_ptr(&second.constants));
+    }
+
+    #[test]
+    fn test_deferred_globals() {
+        // g は f の本体をコンパイルした時点で大域変数の位置を割り当てられる
+        let tests = vec![
+            (
+                "let f = fn() { g }; let g = 1; g",
+                vec![
+                    Object::String(String::from("g")),
+                    function(
+                        vec![
+                            make(Opcode::GetDeferredGlobal, &[0, 0]),
+                            make(Opcode::ReturnValue, &[]),
+                        ],
+                        0,
+                        0,
+                    ),
+                    Object::Integer(1),
+                ],
+                vec![
+                    make(Opcode::Closure, &[1, 0]),
+                    make(Opcode::SetGlobal, &[1]),
+                    make(Opcode::Constant, &[2]),
+                    make(Opcode::SetGlobal, &[0]),
+                    make(Opcode::GetGlobal, &[0]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+            (
+                "if (false) { zz }",
+                vec![Object::String(String::from("zz"))],
+                vec![
+                    make(Opcode::False, &[]),
+                    make(Opcode::JumpNotTruthy, &[12]),
+                    make(Opcode::GetDeferredGlobal, &[0, 0]),
+                    make(Opcode::Jump, &[13]),
+                    make(Opcode::Null, &[]),
+                    make(Opcode::Pop, &[]),
+                ],
+            ),
+        ];
+
+        run_compiler_tests(tests);
+    }
+
+    #[test]
+    fn test_statement_source_map() {
+        // let 文の SetGlobal は、式ではなく文全体の位置を指す
+        let input = "let x = 1;\nif (x) { let y = 2; }";
+        let program = parse(input).unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).unwrap();
+
+        let source_map = compiler.bytecode().source_map;
+        let spans: Vec<&str> = [0, 3, 6, 12, 15]
+            .iter()
+            .map(|offset| {
+                let span = source_map.find(*offset).unwrap();
+                &input[span.start..span.end]
+            })
+            .collect();
+        assert_eq!(
+            vec!["1", "let x = 1", "x", "2", "let y = 2"],
+            spans,
+            "{:?}",
+            source_map
+        );
+    }
+
+    #[test]
+    fn test_rollback_on_error() {
+        let mut compiler = Compiler::new();
+        compiler.compile(&parse("let a = 1;").unwrap()).unwrap();
+        compiler.take_bytecode();
+
+        // 引数が多すぎて OpCall のオペランドに収まらない
+        let input = format!(
+            r#"let b = "b"; let a = fn() {{ c }}; len({})"#,
+            vec!["1"; 256].join(", ")
+        );
+        assert!(compiler.compile(&parse(&input).unwrap()).is_err());
+        assert_eq!(None, compiler.global_index("b"));
+        assert_eq!(None, compiler.global_index("c"));
+
+        compiler.compile(&parse("let e = 2; a").unwrap()).unwrap();
+        let bytecode = compiler.take_bytecode();
+        assert_eq!(
+            vec![Object::Integer(1), Object::Integer(2)],
+            *bytecode.constants
+        );
+        assert_eq!(
+            concat(vec![
+                make(Opcode::Constant, &[1]),
+                make(Opcode::SetGlobal, &[1]),
+                make(Opcode::GetGlobal, &[0]),
+                make(Opcode::Pop, &[]),
+            ]),
+            bytecode.instructions
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt::Write;
+
+use crate::{
+    parser::ParseError,
+    token::{Span, TokenType},
+};
+
+// エラーメッセージとその位置、補足情報をまとめたもの。rustc 風のテキストに整形して表示する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub helps: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            helps: vec![],
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.helps.push(help.into());
+        self
+    }
+
+    pub fn from_parse_error(error: &ParseError, source: &str) -> Self {
+        let diagnostic = Diagnostic::new(error.to_string(), error.span());
+        match parse_error_help(error, source) {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
+
+    // error: <message>
+    //  --> <file>:<line>:<column>
+    //   |
+    // 2 | let y 2;
+    //   |       ^
+    //   |
+    //   = help: ...
+    //
+    // span が source の範囲外であれば、該当行の表示を省く
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut out = String::new();
+        writeln!(out, "error: {}", self.message).unwrap();
+        writeln!(
+            out,
+            "{}--> {}:{}:{}",
+            gutter, file_name, self.span.line, self.span.column
+        )
+        .unwrap();
+        if let Some(line) = source_line(source, self.span) {
+            writeln!(out, "{} |", gutter).unwrap();
+            writeln!(out, "{} | {}", line_number, line.text).unwrap();
+            writeln!(
+                out,
+                "{} | {}{}",
+                gutter,
+                " ".repeat(line.caret_offset),
+                "^".repeat(line.caret_len)
+            )
+            .unwrap();
+        }
+        if !self.helps.is_empty() {
+            writeln!(out, "{} |", gutter).unwrap();
+            for help in self.helps.iter() {
+                writeln!(out, "{} = help: {}", gutter, help).unwrap();
+            }
+        }
+        out
+    }
+}
+
+struct SourceLine<'a> {
+    text: &'a str,
+    caret_offset: usize,
+    caret_len: usize,
+}
+
+// 別のソースから来た span でも落ちないよう、位置は文字の境界に切り下げる
+fn source_line(source: &str, span: Span) -> Option<SourceLine<'_>> {
+    if span.start > source.len() {
+        return None;
+    }
+    let start = floor_char_boundary(source, span.start);
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let end = floor_char_boundary(source, span.end.clamp(start, line_end));
+
+    Some(SourceLine {
+        text: source[line_start..line_end].trim_end_matches('\r'),
+        caret_offset: source[line_start..start].chars().count(),
+        caret_len: source[start..end].chars().count().max(1),
+    })
+}
+
+fn floor_char_boundary(source: &str, index: usize) -> usize {
+    (0..=index.min(source.len()))
+        .rev()
+        .find(|i| source.is_char_boundary(*i))
+        .unwrap_or(0)
+}
+
+fn parse_error_help(error: &ParseError, source: &str) -> Option<String> {
+    match error {
+        ParseError::UnexpectedToken { expected, span, .. } => match expected {
+            TokenType::Assign => {
+                // 直前のテキストが `let x` であれば、その束縛名を示す
+                let start = floor_char_boundary(source, span.start);
+                let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+                let before = source[line_start..start].trim();
+                match before.rfind("let ") {
+                    Some(i) => Some(format!("did you forget `=` after `{}`?", &before[i..])),
+                    None => Some(String::from("did you forget `=`?")),
+                }
+            }
+            TokenType::Ident => Some(String::from("expected a name here")),
+            TokenType::RParen => Some(String::from("did you forget a closing `)`?")),
+            TokenType::LBrace => Some(String::from("a block must start with `{`")),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+    use crate::{lexer::Lexer, parser::Parser, token::Span};
+
+    fn parse_diagnostics(input: &str) -> Vec<Diagnostic> {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        parser
+            .parse_program()
+            .unwrap_err()
+            .iter()
+            .map(|error| Diagnostic::from_parse_error(error, input))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_parse_error() {
+        let input = "let x = 1;\nlet y 2;\n";
+        let diagnostics = parse_diagnostics(input);
+
+        let expected = "\
+error: expected next token to be Assign, got Int instead
+ --> script.mk:2:7
+  |
+2 | let y 2;
+  |       ^
+  |
+  = help: did you forget `=` after `let y`?
+";
+        assert_eq!(expected, diagnostics[0].render("script.mk", input));
+    }
+
+    #[test]
+    fn test_render_underlines_whole_span() {
+        let input = "let a = 1;\n\n\n\n\n\n\n\n\nlet total = a + true;";
+        let span = Span {
+            start: 31,
+            end: 39,
+            line: 10,
+            column: 13,
+        };
+        let diagnostic = Diagnostic::new("type mismatch: INTEGER + BOOLEAN", span);
+
+        let expected = "\
+error: type mismatch: INTEGER + BOOLEAN
+  --> <repl>:10:13
+   |
+10 | let total = a + true;
+   |             ^^^^^^^^
+";
+        assert_eq!(expected, diagnostic.render("<repl>", input));
+    }
+
+    #[test]
+    fn test_render_error_at_end_of_input() {
+        let input = "let sum = add(1, 2";
+        let diagnostics = parse_diagnostics(input);
+
+        let expected = "\
+error: expected next token to be RParen, got Eof instead
+ --> <repl>:1:19
+  |
+1 | let sum = add(1, 2
+  |                   ^
+  |
+  = help: did you forget a closing `)`?
+";
+        assert_eq!(expected, diagnostics[0].render("<repl>", input));
+    }
+
+    #[test]
+    fn test_render_span_from_other_source() {
+        // 「あ」の途中から始まり、途中で終わる span
+        let input = "\"aあああ\"; f(1)";
+        let span = Span {
+            start: 3,
+            end: 6,
+            line: 1,
+            column: 3,
+        };
+        let expected = "\
+error: type mismatch: INTEGER + BOOLEAN
+ --> <repl>:1:3
+  |
+1 | \"aあああ\"; f(1)
+  |   ^
+";
+        let diagnostic = Diagnostic::new("type mismatch: INTEGER + BOOLEAN", span);
+        assert_eq!(expected, diagnostic.render("<repl>", input));
+
+        // 範囲外の span は該当行を表示しない
+        let span = Span {
+            start: 40,
+            end: 48,
+            line: 3,
+            column: 1,
+        };
+        let expected = "\
+error: type mismatch: INTEGER + BOOLEAN
+ --> <repl>:3:1
+";
+        let diagnostic = Diagnostic::new("type mismatch: INTEGER + BOOLEAN", span);
+        assert_eq!(expected, diagnostic.render("<repl>", input));
+    }
+}
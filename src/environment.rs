@@ -0,0 +1,67 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{builtins::Builtins, object::Object};
+
+#[derive(Default)]
+pub struct Environment {
+    store: HashMap<String, Object>,
+    outer: Option<Rc<RefCell<Environment>>>,
+    builtins: Rc<Builtins>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_builtins(builtins: Builtins) -> Self {
+        Self {
+            store: HashMap::new(),
+            outer: None,
+            builtins: Rc::new(builtins),
+        }
+    }
+
+    // 関数呼び出し時に、定義された時点の環境を外側に持つ新しい環境を作る
+    pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Self {
+        let builtins = outer.borrow().builtins.clone();
+        Self {
+            store: HashMap::new(),
+            outer: Some(outer),
+            builtins,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        match self.store.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.outer.as_ref()?.borrow().get(name),
+        }
+    }
+
+    pub fn get_builtin(&self, name: &str) -> Option<Object> {
+        self.builtins.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: Object) {
+        self.store.insert(String::from(name), value);
+    }
+}
+
+// クロージャは自身を束縛した環境を参照するため循環しうる。中身は辿らずに同一性だけで比較・表示する
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.store.keys().collect();
+        names.sort();
+        f.debug_struct("Environment")
+            .field("names", &names)
+            .field("has_outer", &self.outer.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,972 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    convert::TryFrom,
+    rc::Rc,
+};
+
+use thiserror::Error;
+
+use crate::{
+    ast::{Expression, ExpressionKind, Program, Statement, StatementKind},
+    environment::Environment,
+    object::{HashPair, Hashable, Object},
+    RED_ZONE, STACK_SEGMENT,
+};
+
+// 関数呼び出しの入れ子の上限。VM の MAX_FRAMES と同程度で打ち切り、"stack overflow" エラーにする
+const MAX_CALL_DEPTH: usize = 1024;
+
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// 関数本体の評価中だけ呼び出しの深さを 1 つ増やす
+struct CallDepthGuard;
+
+impl CallDepthGuard {
+    fn enter() -> Option<Self> {
+        CALL_DEPTH.with(|depth| {
+            if depth.get() >= MAX_CALL_DEPTH {
+                return None;
+            }
+            depth.set(depth.get() + 1);
+            Some(CallDepthGuard)
+        })
+    }
+}
+
+impl Drop for CallDepthGuard {
+    fn drop(&mut self) {
+        CALL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RuntimeError {
+    #[error("wrong number of arguments: want={want}, got={got}")]
+    WrongArgumentCount { want: usize, got: usize },
+    #[error("argument to `{function}` must be {expected}, got {actual}")]
+    ArgumentType {
+        function: String,
+        expected: &'static str,
+        actual: &'static str,
+    },
+    #[error("argument to `{function}` not supported, got {actual}")]
+    UnsupportedArgument {
+        function: String,
+        actual: &'static str,
+    },
+    #[error("{0}")]
+    Custom(String),
+}
+
+pub fn eval(program: &Program, env: &Rc<RefCell<Environment>>) -> Object {
+    let mut result = Object::Null;
+
+    for statement in program.statements.iter() {
+        result = eval_statement(statement, env);
+
+        match result {
+            Object::ReturnValue(value) => return *value,
+            Object::Error { .. } => return result,
+            _ => {}
+        }
+    }
+
+    result
+}
+
+fn eval_block_statement(statements: &[Statement], env: &Rc<RefCell<Environment>>) -> Object {
+    let mut result = Object::Null;
+
+    for statement in statements.iter() {
+        result = eval_statement(statement, env);
+
+        // ネストしたブロックから外側まで return を伝播させるため、ここでは ReturnValue を剥がさない
+        if matches!(result, Object::ReturnValue(_) | Object::Error { .. }) {
+            return result;
+        }
+    }
+
+    result
+}
+
+fn eval_statement(statement: &Statement, env: &Rc<RefCell<Environment>>) -> Object {
+    match &statement.kind {
+        StatementKind::Expression(expression) => eval_expression(expression, env),
+        StatementKind::Return(value) => {
+            let value = eval_expression(value, env);
+            if value.is_error() {
+                return value;
+            }
+            Object::ReturnValue(Box::new(value))
+        }
+        StatementKind::Let { name, value } => {
+            let value = eval_expression(value, env);
+            if value.is_error() {
+                return value;
+            }
+            if let ExpressionKind::Identifier(name) = &name.kind {
+                env.borrow_mut().set(name, value);
+            }
+            Object::Null
+        }
+        StatementKind::Block(statements) => eval_block_statement(statements, env),
+    }
+}
+
+fn eval_expression(expression: &Expression, env: &Rc<RefCell<Environment>>) -> Object {
+    // 式の評価中に発生したエラーには、その式の位置を持たせる
+    let result = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+        eval_expression_kind(&expression.kind, env)
+    });
+    match result {
+        Ok(value) => value,
+        Err(message) => Object::Error {
+            message,
+            span: expression.span,
+        },
+    }
+}
+
+fn eval_expression_kind(
+    kind: &ExpressionKind,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Object, String> {
+    match kind {
+        ExpressionKind::IntegerLiteral(value) => Ok(Object::Integer(*value)),
+        ExpressionKind::Boolean(value) => Ok(Object::Boolean(*value)),
+        ExpressionKind::StringLiteral(value) => Ok(Object::String(value.clone())),
+        ExpressionKind::Array(elements) => match eval_expressions(elements, env) {
+            Ok(elements) => Ok(Object::Array(elements)),
+            Err(error) => Ok(error),
+        },
+        ExpressionKind::Hash(pairs) => eval_hash_literal(pairs, env),
+        ExpressionKind::Identifier(name) => eval_identifier(name, env),
+        ExpressionKind::Prefix { operator, right } => {
+            let right = eval_expression(right, env);
+            if right.is_error() {
+                return Ok(right);
+            }
+            eval_prefix_expression(operator, right)
+        }
+        ExpressionKind::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let left = eval_expression(left, env);
+            if left.is_error() {
+                return Ok(left);
+            }
+            let right = eval_expression(right, env);
+            if right.is_error() {
+                return Ok(right);
+            }
+            eval_infix_expression(operator, left, right)
+        }
+        ExpressionKind::If {
+            condition,
+            consequence,
+            alternative,
+        } => {
+            let condition = eval_expression(condition, env);
+            if condition.is_error() {
+                return Ok(condition);
+            }
+
+            if condition.is_truthy() {
+                Ok(eval_statement(consequence, env))
+            } else if let Some(alternative) = alternative {
+                Ok(eval_statement(alternative, env))
+            } else {
+                Ok(Object::Null)
+            }
+        }
+        ExpressionKind::Function { parameters, body } => Ok(Object::Function {
+            parameters: parameters.clone(),
+            body: body.clone(),
+            env: Rc::clone(env),
+        }),
+        ExpressionKind::Call {
+            function,
+            arguments,
+        } => {
+            let function = eval_expression(function, env);
+            if function.is_error() {
+                return Ok(function);
+            }
+            let arguments = match eval_expressions(arguments, env) {
+                Ok(arguments) => arguments,
+                Err(error) => return Ok(error),
+            };
+            apply_function(function, arguments)
+        }
+        ExpressionKind::Index { left, index } => {
+            let left = eval_expression(left, env);
+            if left.is_error() {
+                return Ok(left);
+            }
+            let index = eval_expression(index, env);
+            if index.is_error() {
+                return Ok(index);
+            }
+            eval_index_expression(left, index)
+        }
+    }
+}
+
+fn eval_expressions(
+    expressions: &[Expression],
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Vec<Object>, Object> {
+    let mut result = vec![];
+
+    for expression in expressions.iter() {
+        let evaluated = eval_expression(expression, env);
+        if evaluated.is_error() {
+            return Err(evaluated);
+        }
+        result.push(evaluated);
+    }
+
+    Ok(result)
+}
+
+fn eval_identifier(name: &str, env: &Rc<RefCell<Environment>>) -> Result<Object, String> {
+    let env = env.borrow();
+    if let Some(value) = env.get(name) {
+        return Ok(value);
+    }
+    match env.get_builtin(name) {
+        Some(builtin) => Ok(builtin),
+        None => Err(format!("identifier not found: {}", name)),
+    }
+}
+
+fn apply_function(function: Object, arguments: Vec<Object>) -> Result<Object, String> {
+    match function {
+        Object::Function {
+            parameters,
+            body,
+            env,
+        } => {
+            if parameters.len() != arguments.len() {
+                return Err(format!(
+                    "wrong number of arguments: want={}, got={}",
+                    parameters.len(),
+                    arguments.len()
+                ));
+            }
+
+            let _guard = CallDepthGuard::enter().ok_or_else(|| String::from("stack overflow"))?;
+            let mut extended_env = Environment::new_enclosed(env);
+            for (parameter, argument) in parameters.iter().zip(arguments) {
+                extended_env.set(parameter, argument);
+            }
+
+            let env = Rc::new(RefCell::new(extended_env));
+            match eval_statement(&body, &env) {
+                Object::ReturnValue(value) => Ok(*value),
+                evaluated => Ok(evaluated),
+            }
+        }
+        Object::Builtin(builtin) => builtin.call(&arguments).map_err(|error| error.to_string()),
+        _ => Err(format!("not a function: {}", function.type_name())),
+    }
+}
+
+fn eval_hash_literal(
+    pairs: &[(Expression, Expression)],
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Object, String> {
+    let mut hash = HashMap::new();
+
+    for (key, value) in pairs {
+        let key = eval_expression(key, env);
+        if key.is_error() {
+            return Ok(key);
+        }
+        let hash_key = key
+            .hash_key()
+            .ok_or_else(|| format!("unusable as hash key: {}", key.type_name()))?;
+        let value = eval_expression(value, env);
+        if value.is_error() {
+            return Ok(value);
+        }
+        hash.insert(hash_key, HashPair { key, value });
+    }
+
+    Ok(Object::Hash(hash))
+}
+
+pub(crate) fn eval_index_expression(left: Object, index: Object) -> Result<Object, String> {
+    match (&left, &index) {
+        (Object::Hash(pairs), _) => {
+            let hash_key = index
+                .hash_key()
+                .ok_or_else(|| format!("unusable as hash key: {}", index.type_name()))?;
+            Ok(pairs
+                .get(&hash_key)
+                .map(|pair| pair.value.clone())
+                .unwrap_or(Object::Null))
+        }
+        (Object::Array(elements), Object::Integer(i)) => Ok(usize::try_from(*i)
+            .ok()
+            .and_then(|i| elements.get(i))
+            .cloned()
+            .unwrap_or(Object::Null)),
+        _ => Err(format!(
+            "index operator not supported: {}[{}]",
+            left.type_name(),
+            index.type_name()
+        )),
+    }
+}
+
+pub(crate) fn eval_prefix_expression(operator: &str, right: Object) -> Result<Object, String> {
+    match operator {
+        "!" => Ok(Object::Boolean(!right.is_truthy())),
+        "-" => match right {
+            Object::Integer(value) => Ok(Object::Integer(value.wrapping_neg())),
+            _ => Err(format!("unknown operator: -{}", right.type_name())),
+        },
+        _ => Err(format!(
+            "unknown operator: {}{}",
+            operator,
+            right.type_name()
+        )),
+    }
+}
+
+pub(crate) fn eval_infix_expression(
+    operator: &str,
+    left: Object,
+    right: Object,
+) -> Result<Object, String> {
+    match (&left, &right) {
+        (Object::Integer(l), Object::Integer(r)) => eval_integer_infix_expression(operator, *l, *r),
+        (Object::String(l), Object::String(r)) => eval_string_infix_expression(operator, l, r),
+        (Object::Boolean(l), Object::Boolean(r)) if operator == "==" => Ok(Object::Boolean(l == r)),
+        (Object::Boolean(l), Object::Boolean(r)) if operator == "!=" => Ok(Object::Boolean(l != r)),
+        _ if left.type_name() != right.type_name() => Err(format!(
+            "type mismatch: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        )),
+        _ => Err(format!(
+            "unknown operator: {} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        )),
+    }
+}
+
+fn eval_string_infix_expression(operator: &str, left: &str, right: &str) -> Result<Object, String> {
+    match operator {
+        "+" => Ok(Object::String(format!("{}{}", left, right))),
+        "==" => Ok(Object::Boolean(left == right)),
+        "!=" => Ok(Object::Boolean(left != right)),
+        _ => Err(format!("unknown operator: STRING {} STRING", operator)),
+    }
+}
+
+fn eval_integer_infix_expression(operator: &str, left: i64, right: i64) -> Result<Object, String> {
+    match operator {
+        "+" => Ok(Object::Integer(left.wrapping_add(right))),
+        "-" => Ok(Object::Integer(left.wrapping_sub(right))),
+        "*" => Ok(Object::Integer(left.wrapping_mul(right))),
+        "/" if right == 0 => Err(String::from("division by zero")),
+        // 他の算術演算と同じく、i64::MIN / -1 は桁あふれして i64::MIN になる
+        "/" => Ok(Object::Integer(left.wrapping_div(right))),
+        "<" => Ok(Object::Boolean(left < right)),
+        ">" => Ok(Object::Boolean(left > right)),
+        "==" => Ok(Object::Boolean(left == right)),
+        "!=" => Ok(Object::Boolean(left != right)),
+        _ => Err(format!("unknown operator: INTEGER {} INTEGER", operator)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::eval;
+    use crate::{
+        ast::Program,
+        compiler::Compiler,
+        environment::Environment,
+        lexer::Lexer,
+        object::{HashKey, Object},
+        parser::Parser,
+        token::Span,
+        vm::Vm,
+    };
+
+    // すべての評価器のテストは VM でも実行し、両者の結果が一致することを確かめる
+    fn test_eval(input: &str) -> Object {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().expect("failed to parse input");
+        let env = Rc::new(RefCell::new(Environment::new()));
+
+        let evaluated = eval(&program, &env);
+        assert_same_as_vm(input, &evaluated, run_vm(&program));
+        evaluated
+    }
+
+    fn run_vm(program: &Program) -> Result<Object, String> {
+        let mut compiler = Compiler::new();
+        compiler
+            .compile(program)
+            .map_err(|error| error.to_string())?;
+
+        let mut vm = Vm::new(compiler.bytecode());
+        vm.run().map_err(|error| error.to_string())?;
+        Ok(vm.last_popped_stack_elem().clone())
+    }
+
+    fn assert_same_as_vm(input: &str, evaluated: &Object, vm_result: Result<Object, String>) {
+        match (evaluated, &vm_result) {
+            // エラーの位置は評価器だけが持つので、メッセージだけを比べる
+            (Object::Error { message, .. }, Err(vm_message)) => {
+                assert_eq!(message, vm_message, "{}", input)
+            }
+            // 関数は表現が異なるので、どちらも関数であることだけを確かめる
+            (Object::Function { .. }, Ok(Object::Closure(_))) => {}
+            (_, Ok(vm_value)) => assert_eq!(evaluated, vm_value, "{}", input),
+            _ => panic!(
+                "engines disagree on {}: eval={:?}, vm={:?}",
+                input, evaluated, vm_result
+            ),
+        }
+    }
+
+    #[test]
+    fn test_eval_integer_expression() {
+        let tests = vec![
+            ("5", 5),
+            ("10", 10),
+            ("-5", -5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("-50 + 100 + -50", 0),
+            ("5 * 2 + 10", 20),
+            ("5 + 2 * 10", 25),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("3 * 3 * 3 + 10", 37),
+            ("3 * (3 * 3) + 10", 37),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+            ("9223372036854775807 + 1", i64::MIN),
+            ("(-9223372036854775807 - 1) / -1", i64::MIN),
+            ("-7 / 2", -3),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::Integer(expected), test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_boolean_expression() {
+        let tests = vec![
+            ("true", true),
+            ("false", false),
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 < 1", false),
+            ("1 > 1", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("1 == 2", false),
+            ("1 != 2", true),
+            ("true == true", true),
+            ("false == false", true),
+            ("true == false", false),
+            ("true != false", true),
+            (r#""a" == "a""#, true),
+            (r#""a" != "a""#, false),
+            ("(1 < 2) == true", true),
+            ("(1 < 2) == false", false),
+            ("(1 > 2) == true", false),
+            ("(1 > 2) == false", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::Boolean(expected), test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_string_literal() {
+        let input = r#""Hello World!""#;
+
+        assert_eq!(
+            Object::String(String::from("Hello World!")),
+            test_eval(input)
+        );
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        let tests = vec![
+            (r#""Hello" + " " + "World!""#, "Hello World!"),
+            (
+                r#"let greet = fn(name) { "Hello, " + name + "\n" }; greet("Monkey")"#,
+                "Hello, Monkey\n",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::String(String::from(expected)), test_eval(input));
+        }
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let tests = vec![
+            (r#"len("")"#, Object::Integer(0)),
+            (r#"len("four")"#, Object::Integer(4)),
+            (r#"len("こんにちは")"#, Object::Integer(5)),
+            ("len([1, 2, 3])", Object::Integer(3)),
+            ("len([])", Object::Integer(0)),
+            ("first([1, 2, 3])", Object::Integer(1)),
+            ("first([])", Object::Null),
+            ("last([1, 2, 3])", Object::Integer(3)),
+            ("last([])", Object::Null),
+            (
+                "rest([1, 2, 3])",
+                Object::Array(vec![Object::Integer(2), Object::Integer(3)]),
+            ),
+            ("rest([])", Object::Null),
+            ("push([], 1)", Object::Array(vec![Object::Integer(1)])),
+            (
+                "let a = [1]; let b = push(a, 2); a",
+                Object::Array(vec![Object::Integer(1)]),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_builtin_function_errors() {
+        let tests = vec![
+            ("len(1)", "argument to `len` not supported, got INTEGER"),
+            (
+                r#"len("one", "two")"#,
+                "wrong number of arguments: want=1, got=2",
+            ),
+            ("first(1)", "argument to `first` must be ARRAY, got INTEGER"),
+            ("last(1)", "argument to `last` must be ARRAY, got INTEGER"),
+            ("rest(1)", "argument to `rest` must be ARRAY, got INTEGER"),
+            (
+                "push(1, 1)",
+                "argument to `push` must be ARRAY, got INTEGER",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error { message, .. } => assert_eq!(expected, message, "{}", input),
+                evaluated => panic!("no error object returned. got {:?}", evaluated),
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let input = r#"
+    let two = "two";
+    {
+        "one": 10 - 9,
+        two: 1 + 1,
+        "thr" + "ee": 6 / 2,
+        4: 4,
+        true: 5,
+        false: 6
+    }
+    "#;
+
+        let expected = vec![
+            (HashKey::String(String::from("one")), Object::Integer(1)),
+            (HashKey::String(String::from("two")), Object::Integer(2)),
+            (HashKey::String(String::from("three")), Object::Integer(3)),
+            (HashKey::Integer(4), Object::Integer(4)),
+            (HashKey::Boolean(true), Object::Integer(5)),
+            (HashKey::Boolean(false), Object::Integer(6)),
+        ];
+
+        match test_eval(input) {
+            Object::Hash(pairs) => {
+                assert_eq!(expected.len(), pairs.len());
+                for (key, value) in expected {
+                    assert_eq!(value, pairs[&key].value);
+                }
+            }
+            evaluated => panic!("object is not Hash. got {:?}", evaluated),
+        }
+    }
+
+    #[test]
+    fn test_hash_index_expressions() {
+        let tests = vec![
+            (r#"{"foo": 5}["foo"]"#, Object::Integer(5)),
+            (r#"{"foo": 5}["bar"]"#, Object::Null),
+            (r#"let key = "foo"; {"foo": 5}[key]"#, Object::Integer(5)),
+            (r#"{}["foo"]"#, Object::Null),
+            ("{5: 5}[5]", Object::Integer(5)),
+            ("{true: 5}[true]", Object::Integer(5)),
+            ("{false: 5}[false]", Object::Integer(5)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_literals() {
+        let input = "[1, 2 * 2, 3 + 3]";
+
+        assert_eq!(
+            Object::Array(vec![
+                Object::Integer(1),
+                Object::Integer(4),
+                Object::Integer(6)
+            ]),
+            test_eval(input)
+        );
+    }
+
+    #[test]
+    fn test_array_index_expressions() {
+        let tests = vec![
+            ("[1, 2, 3][0]", Object::Integer(1)),
+            ("[1, 2, 3][1]", Object::Integer(2)),
+            ("[1, 2, 3][2]", Object::Integer(3)),
+            ("let i = 0; [1][i];", Object::Integer(1)),
+            ("[1, 2, 3][1 + 1];", Object::Integer(3)),
+            ("let myArray = [1, 2, 3]; myArray[2];", Object::Integer(3)),
+            (
+                "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+                Object::Integer(6),
+            ),
+            (
+                "let myArray = [1, 2, 3]; let i = myArray[0]; myArray[i]",
+                Object::Integer(2),
+            ),
+            ("[1, 2, 3][3]", Object::Null),
+            ("[1, 2, 3][-1]", Object::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_map_reduce() {
+        let input = "
+    let map = fn(arr, f) {
+        let iter = fn(arr, accumulated) {
+            if (len(arr) == 0) {
+                accumulated
+            } else {
+                iter(rest(arr), push(accumulated, f(first(arr))));
+            }
+        };
+        iter(arr, []);
+    };
+    let reduce = fn(arr, initial, f) {
+        let iter = fn(arr, result) {
+            if (len(arr) == 0) {
+                result
+            } else {
+                iter(rest(arr), f(result, first(arr)));
+            }
+        };
+        iter(arr, initial);
+    };
+    let double = fn(x) { x * 2 };
+    reduce(map([1, 2, 3, 4], double), 0, fn(a, b) { a + b });
+    ";
+
+        assert_eq!(Object::Integer(20), test_eval(input));
+    }
+
+    #[test]
+    fn test_bang_operator() {
+        let tests = vec![
+            ("!true", false),
+            ("!false", true),
+            ("!5", false),
+            ("!!true", true),
+            ("!!false", false),
+            ("!!5", true),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::Boolean(expected), test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        let tests = vec![
+            ("if (1 < 2) { 10 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 }", Object::Null),
+            ("if (1) { 10 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (1 < 2) { 10 } else { 20 }", Object::Integer(10)),
+            ("if (1 < 2) { 10; 11 } else { 20 }", Object::Integer(11)),
+            ("if (1 < 2) { }", Object::Null),
+            // 実行されない分岐の中の未定義の名前はエラーにならない
+            ("if (false) { zz }", Object::Null),
+            ("if (true) { 1 } else { zz }", Object::Integer(1)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let tests = vec![
+            ("return 10;", 10),
+            ("return 10; 9;", 10),
+            ("return 2 * 5; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+            (
+                "if (10 > 1) {
+                    if (10 > 1) {
+                        return 10;
+                    }
+                    return 1;
+                }",
+                10,
+            ),
+            (
+                "let f = fn(x) {
+                    if (x > 1) {
+                        if (x > 2) {
+                            return x * 2;
+                        }
+                    }
+                    return 0;
+                };
+                f(3);",
+                6,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::Integer(expected), test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let input = "let f = fn(x) { f(x + 1) }; f(0)";
+        match test_eval(input) {
+            Object::Error { message, .. } => assert_eq!("stack overflow", message),
+            evaluated => panic!("expected stack overflow, got {:?}", evaluated),
+        }
+
+        // 相互再帰でも同じく打ち切られる
+        let input = "let a = fn(x) { b(x + 1) }; let b = fn(x) { a(x + 1) }; a(0)";
+        match test_eval(input) {
+            Object::Error { message, .. } => assert_eq!("stack overflow", message),
+            evaluated => panic!("expected stack overflow, got {:?}", evaluated),
+        }
+
+        // 上限より浅い再帰は、打ち切られた後でも普通に評価できる
+        let input = "
+let sum = fn(n) {
+  if (n == 0) { return 0; }
+  let rest = sum(n - 1);
+  n + rest
+};
+sum(500)";
+        assert_eq!(Object::Integer(125250), test_eval(input));
+    }
+
+    #[test]
+    fn test_deeply_nested_expression() {
+        // 左結合の演算は構文解析では再帰しないが、評価とコンパイルでは項の数だけ再帰する
+        let input = vec!["1"; 100_000].join(" + ");
+        assert_eq!(Object::Integer(100_000), test_eval(&input));
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let tests = vec![
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN"),
+            ("5 + true; 5;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "unknown operator: -BOOLEAN"),
+            ("true + false;", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("5; true + false; 5", "unknown operator: BOOLEAN + BOOLEAN"),
+            (
+                "if (10 > 1) { if (10 > 1) { return true + false; } return 1; }",
+                "unknown operator: BOOLEAN + BOOLEAN",
+            ),
+            ("1 / 0", "division by zero"),
+            ("foobar", "identifier not found: foobar"),
+            (
+                "let f = fn(x) { x }; f(1, 2)",
+                "wrong number of arguments: want=1, got=2",
+            ),
+            ("let x = 5; x(1)", "not a function: INTEGER"),
+            ("1[0]", "index operator not supported: INTEGER[INTEGER]"),
+            (
+                r#"{"name": "Monkey"}[fn(x) { x }];"#,
+                "unusable as hash key: FUNCTION",
+            ),
+            ("{fn(x) { x }: 1};", "unusable as hash key: FUNCTION"),
+            ("{[1]: 1};", "unusable as hash key: ARRAY"),
+            (r#""Hello" - "World""#, "unknown operator: STRING - STRING"),
+            (r#""a" + 1"#, "type mismatch: STRING + INTEGER"),
+            ("let f = fn() { g() }; f()", "identifier not found: g"),
+            ("let q = 1; q + zz", "identifier not found: zz"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input) {
+                Object::Error { message, .. } => assert_eq!(expected, message, "{}", input),
+                evaluated => panic!("no error object returned. got {:?}", evaluated),
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_span() {
+        let tests = vec![
+            ("let x = 5;\nx + true;", 11, 19, 2, 1),
+            ("let f = fn(x) {\n  -x\n};\nf(true)", 18, 20, 2, 3),
+            ("1 + (2 +\n  foo)", 11, 14, 2, 3),
+        ];
+
+        for (input, start, end, line, column) in tests {
+            match test_eval(input) {
+                Object::Error { span, .. } => assert_eq!(
+                    Span {
+                        start,
+                        end,
+                        line,
+                        column
+                    },
+                    span,
+                    "{}",
+                    input
+                ),
+                evaluated => panic!("no error object returned. got {:?}", evaluated),
+            }
+        }
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let tests = vec![
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; a;", 25),
+            ("let a = 5; let b = a; b;", 5),
+            ("let a = 5; let b = a; let c = a + b + 5; c;", 15),
+            // 関数は呼び出した時点の大域変数を参照する
+            ("let x = 1; let f = fn() { x }; let x = 2; f()", 2),
+            ("let a = fn() { b() }; let b = fn() { 1 }; a()", 1),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::Integer(expected), test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_function_object() {
+        let input = "fn(x) { x + 2; };";
+
+        match test_eval(input) {
+            Object::Function {
+                parameters, body, ..
+            } => {
+                assert_eq!(vec!["x"], parameters);
+                assert_eq!("{ (x + 2) }", body.to_string());
+            }
+            evaluated => panic!("object is not Function. got {:?}", evaluated),
+        }
+    }
+
+    #[test]
+    fn test_function_application() {
+        let tests = vec![
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2; }; double(5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5, 5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x; }(5)", 5),
+            (
+                "let f = fn(x) { return x; 10; }; let g = fn() { f(1) + 1; }; g();",
+                2,
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Object::Integer(expected), test_eval(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = "
+    let newAdder = fn(x) {
+        fn(y) { x + y };
+    };
+
+    let addTwo = newAdder(2);
+    addTwo(2);
+    ";
+
+        assert_eq!(Object::Integer(4), test_eval(input));
+    }
+
+    #[test]
+    fn test_lexer_sample_program() {
+        let input = "
+    let five = 5;
+    let ten = 10;
+
+    let add = fn(x, y) {
+        x + y;
+    };
+
+    let result = add(five, ten);
+    result;
+    ";
+
+        assert_eq!(Object::Integer(15), test_eval(input));
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let input = "
+    let countdown = fn(x) {
+        return countdown;
+    };
+    countdown(1)(2)(3);
+    ";
+
+        match test_eval(input) {
+            Object::Function { .. } => {}
+            evaluated => panic!("object is not Function. got {:?}", evaluated),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+use std::{cell::RefCell, collections::HashMap, fmt, mem, rc::Rc, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{
+    builtins::Builtins,
+    compiler::{CompileError, Compiler},
+    environment::Environment,
+    evaluator,
+    lexer::Lexer,
+    object::{HashKey, HashPair, Object},
+    parser::{ParseErrors, Parser},
+    token::Span,
+    vm::{Vm, VmError},
+};
+
+pub type Value = Object;
+
+#[derive(Debug, Clone, Error)]
+pub enum Error {
+    #[error("{0}")]
+    Parse(ParseErrors),
+    #[error("{}:{}: {message}", span.line, span.column)]
+    Runtime { message: String, span: Span },
+    #[error("{}:{}: {0}", .0.span().line, .0.span().column)]
+    Compile(CompileError),
+    // 位置が分からない VM の実行時エラー
+    #[error("{0}")]
+    Vm(VmError),
+    #[error("cannot convert {actual} into {expected}")]
+    Conversion {
+        expected: &'static str,
+        actual: &'static str,
+    },
+}
+
+// 2 つのエンジンは次の点で結果が異なる
+// - 評価器は入力の途中でエラーになってもその手前までの文を実行済みだが、VM はコンパイルに
+//   失敗すると (定数や引数が多すぎて命令のオペランドに収まらない場合) 入力全体を実行しない。
+//   未定義の名前はどちらのエンジンでも評価されたときのエラーになる
+// - 関数内のクロージャは、評価器では外側のローカル変数を後から let し直した値を参照するが、
+//   VM ではクロージャを作成した時点の値を参照する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    // 構文木をそのまま辿って評価する
+    Eval,
+    // バイトコードにコンパイルして VM で実行する
+    Vm,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eval" => Ok(Engine::Eval),
+            "vm" => Ok(Engine::Vm),
+            _ => Err(format!("unknown engine `{}`, expected one of vm, eval", s)),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Eval => write!(f, "eval"),
+            Engine::Vm => write!(f, "vm"),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Backend {
+    Eval(Rc<RefCell<Environment>>),
+    Vm {
+        compiler: Box<Compiler>,
+        globals: Vec<Option<Object>>,
+        builtins: Rc<Builtins>,
+    },
+}
+
+// 環境を保持し続けるので、前の eval で定義した束縛を後の eval から参照できる
+#[derive(Debug)]
+pub struct Interpreter {
+    backend: Backend,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_builtins(Builtins::default())
+    }
+
+    pub fn with_builtins(builtins: Builtins) -> Self {
+        Self::with_engine(Engine::Eval, builtins)
+    }
+
+    pub fn with_engine(engine: Engine, builtins: Builtins) -> Self {
+        let backend = match engine {
+            Engine::Eval => {
+                Backend::Eval(Rc::new(RefCell::new(Environment::with_builtins(builtins))))
+            }
+            Engine::Vm => Backend::Vm {
+                compiler: Box::new(Compiler::with_builtins(&builtins)),
+                globals: vec![],
+                builtins: Rc::new(builtins),
+            },
+        };
+        Self { backend }
+    }
+
+    pub fn engine(&self) -> Engine {
+        match self.backend {
+            Backend::Eval(_) => Engine::Eval,
+            Backend::Vm { .. } => Engine::Vm,
+        }
+    }
+
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        self.eval_from(source, 0)
+    }
+
+    // source の start バイト目以降だけを評価する。エラーの位置は source 全体での位置になるので、
+    // 入力を積み重ねておけば、以前の入力で定義した関数の中のエラーも元の入力に対して表示できる
+    pub fn eval_from(&mut self, source: &str, start: usize) -> Result<Value, Error> {
+        let mut parser = Parser::new(Lexer::starting_at(source, start));
+        let program = parser
+            .parse_program()
+            .map_err(|errors| Error::Parse(ParseErrors::from(errors)))?;
+
+        match &mut self.backend {
+            Backend::Eval(env) => match evaluator::eval(&program, env) {
+                Object::Error { message, span } => Err(Error::Runtime { message, span }),
+                value => Ok(value),
+            },
+            Backend::Vm {
+                compiler,
+                globals,
+                builtins,
+            } => {
+                let compiled = compiler.compile(&program);
+                let bytecode = compiler.take_bytecode();
+                compiled.map_err(Error::Compile)?;
+
+                let mut vm = Vm::with_globals(bytecode, builtins.clone(), mem::take(globals));
+                let result = vm.run();
+                let value = vm.last_popped_stack_elem().clone();
+                *globals = vm.into_globals();
+                // 位置が分かれば評価器と同じ形のエラーにする
+                result.map_err(|error| match error.span {
+                    Some(span) => Error::Runtime {
+                        message: error.to_string(),
+                        span,
+                    },
+                    None => Error::Vm(error),
+                })?;
+                Ok(value)
+            }
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: impl IntoMonkey) {
+        match &mut self.backend {
+            Backend::Eval(env) => env.borrow_mut().set(name, value.into_monkey()),
+            Backend::Vm {
+                compiler, globals, ..
+            } => {
+                let index = compiler.define_global(name);
+                if index >= globals.len() {
+                    globals.resize(index + 1, None);
+                }
+                globals[index] = Some(value.into_monkey());
+            }
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match &self.backend {
+            Backend::Eval(env) => env.borrow().get(name),
+            Backend::Vm {
+                compiler, globals, ..
+            } => globals.get(compiler.global_index(name)?).cloned().flatten(),
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait IntoMonkey {
+    fn into_monkey(self) -> Object;
+}
+
+pub trait FromMonkey: Sized {
+    fn from_monkey(value: &Object) -> Result<Self, Error>;
+}
+
+fn conversion_error(expected: &'static str, actual: &Object) -> Error {
+    Error::Conversion {
+        expected,
+        actual: actual.type_name(),
+    }
+}
+
+impl IntoMonkey for Object {
+    fn into_monkey(self) -> Object {
+        self
+    }
+}
+
+impl FromMonkey for Object {
+    fn from_monkey(value: &Object) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoMonkey for i64 {
+    fn into_monkey(self) -> Object {
+        Object::Integer(self)
+    }
+}
+
+impl FromMonkey for i64 {
+    fn from_monkey(value: &Object) -> Result<Self, Error> {
+        match value {
+            Object::Integer(value) => Ok(*value),
+            _ => Err(conversion_error("INTEGER", value)),
+        }
+    }
+}
+
+impl IntoMonkey for bool {
+    fn into_monkey(self) -> Object {
+        Object::Boolean(self)
+    }
+}
+
+impl FromMonkey for bool {
+    fn from_monkey(value: &Object) -> Result<Self, Error> {
+        match value {
+            Object::Boolean(value) => Ok(*value),
+            _ => Err(conversion_error("BOOLEAN", value)),
+        }
+    }
+}
+
+impl IntoMonkey for String {
+    fn into_monkey(self) -> Object {
+        Object::String(self)
+    }
+}
+
+impl IntoMonkey for &str {
+    fn into_monkey(self) -> Object {
+        Object::String(String::from(self))
+    }
+}
+
+impl FromMonkey for String {
+    fn from_monkey(value: &Object) -> Result<Self, Error> {
+        match value {
+            Object::String(value) => Ok(value.clone()),
+            _ => Err(conversion_error("STRING", value)),
+        }
+    }
+}
+
+impl<T: IntoMonkey> IntoMonkey for Vec<T> {
+    fn into_monkey(self) -> Object {
+        Object::Array(self.into_iter().map(IntoMonkey::into_monkey).collect())
+    }
+}
+
+impl<T: FromMonkey> FromMonkey for Vec<T> {
+    fn from_monkey(value: &Object) -> Result<Self, Error> {
+        match value {
+            Object::Array(elements) => elements.iter().map(T::from_monkey).collect(),
+            _ => Err(conversion_error("ARRAY", value)),
+        }
+    }
+}
+
+// 設定値のような用途を想定し、文字列をキーとするハッシュだけを変換対象にする
+impl<V: IntoMonkey> IntoMonkey for HashMap<String, V> {
+    fn into_monkey(self) -> Object {
+        let pairs = self
+            .into_iter()
+            .map(|(key, value)| {
+                let pair = HashPair {
+                    key: Object::String(key.clone()),
+                    value: value.into_monkey(),
+                };
+                (HashKey::String(key), pair)
+            })
+            .collect();
+        Object::Hash(pairs)
+    }
+}
+
+impl<V: FromMonkey> FromMonkey for HashMap<String, V> {
+    fn from_monkey(value: &Object) -> Result<Self, Error> {
+        match value {
+            Object::Hash(pairs) => pairs
+                .values()
+                .map(|pair| {
+                    Ok((
+                        String::from_monkey(&pair.key)?,
+                        V::from_monkey(&pair.value)?,
+                    ))
+                })
+                .collect(),
+            _ => Err(conversion_error("HASH", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Engine, Error, FromMonkey, Interpreter};
+    use crate::{builtins::Builtins, object::Object};
+
+    fn interpreters() -> Vec<Interpreter> {
+        vec![
+            Interpreter::with_engine(Engine::Eval, Builtins::default()),
+            Interpreter::with_engine(Engine::Vm, Builtins::default()),
+        ]
+    }
+
+    #[test]
+    fn test_eval_keeps_bindings() {
+        for mut interpreter in interpreters() {
+            assert_eq!(Object::Null, interpreter.eval("let x = 2;").unwrap());
+            interpreter.eval("let double = fn(n) { n * x };").unwrap();
+            assert_eq!(Object::Integer(10), interpreter.eval("double(5)").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let mut interpreter = Interpreter::new();
+
+        match interpreter.eval("let x 1;") {
+            Err(Error::Parse(errors)) => assert_eq!(1, errors.len()),
+            result => panic!("expected parse error. got {:?}", result),
+        }
+
+        match interpreter.eval("1;\n2 + true") {
+            Err(error @ Error::Runtime { .. }) => {
+                assert_eq!("2:1: type mismatch: INTEGER + BOOLEAN", error.to_string())
+            }
+            result => panic!("expected runtime error. got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_vm_errors() {
+        let mut interpreter = Interpreter::with_engine(Engine::Vm, Builtins::default());
+
+        match interpreter.eval("let x = 1;\ny") {
+            Err(error @ Error::Runtime { .. }) => {
+                assert_eq!("2:1: identifier not found: y", error.to_string())
+            }
+            result => panic!("expected runtime error. got {:?}", result),
+        }
+
+        // 関数の本体にある未定義の名前は、呼び出したときのエラーになる
+        interpreter.eval("let f = fn() { y };").unwrap();
+        match interpreter.eval("f()") {
+            Err(error @ Error::Runtime { .. }) => {
+                assert_eq!("1:16: identifier not found: y", error.to_string())
+            }
+            result => panic!("expected runtime error. got {:?}", result),
+        }
+        interpreter.eval("let y = 3;").unwrap();
+        assert_eq!(Object::Integer(3), interpreter.eval("f()").unwrap());
+
+        // 失敗した入力の後も、それまでの束縛を使い続けられる
+        interpreter.eval("let x = 1;").unwrap();
+        match interpreter.eval("x + true") {
+            Err(error @ Error::Runtime { .. }) => {
+                assert_eq!("1:1: type mismatch: INTEGER + BOOLEAN", error.to_string())
+            }
+            result => panic!("expected runtime error. got {:?}", result),
+        }
+        assert_eq!(Object::Integer(2), interpreter.eval("x + 1").unwrap());
+    }
+
+    #[test]
+    fn test_error_keeps_earlier_bindings() {
+        for mut interpreter in interpreters() {
+            match interpreter.eval("let q = 1; zz") {
+                Err(error @ Error::Runtime { .. }) => {
+                    assert_eq!("1:12: identifier not found: zz", error.to_string())
+                }
+                result => panic!("expected runtime error. got {:?}", result),
+            }
+            assert_eq!(Some(Object::Integer(1)), interpreter.get_global("q"));
+            assert_eq!(None, interpreter.get_global("zz"));
+        }
+    }
+
+    #[test]
+    fn test_engine_divergences() {
+        let mut eval = Interpreter::with_engine(Engine::Eval, Builtins::default());
+        let mut vm = Interpreter::with_engine(Engine::Vm, Builtins::default());
+
+        // 評価器はエラーの手前までの文を実行するが、VM は入力全体のコンパイルに失敗すると何も実行しない
+        let input = format!("let q = 1; len({})", vec!["1"; 256].join(", "));
+        match eval.eval(&input) {
+            Err(error @ Error::Runtime { .. }) => assert_eq!(
+                "1:12: wrong number of arguments: want=1, got=256",
+                error.to_string()
+            ),
+            result => panic!("expected runtime error. got {:?}", result),
+        }
+        match vm.eval(&input) {
+            Err(error @ Error::Compile(_)) => assert_eq!(
+                "1:12: operand 256 of OpCall is out of range (max 255)",
+                error.to_string()
+            ),
+            result => panic!("expected compile error. got {:?}", result),
+        }
+        assert_eq!(Some(Object::Integer(1)), eval.get_global("q"));
+        assert_eq!(None, vm.get_global("q"));
+
+        // 評価器のクロージャは外側の環境を共有するが、VM は作成時のローカル変数の値を取り込む
+        let input = "let f = fn() { let y = 1; let g = fn() { y }; let y = 2; g() }; f()";
+        assert_eq!(Object::Integer(2), eval.eval(input).unwrap());
+        assert_eq!(Object::Integer(1), vm.eval(input).unwrap());
+    }
+
+    #[test]
+    fn test_long_vm_session() {
+        // 同じ定数は使い回されるので、定数プールの添字の上限 (65535) を超えない
+        let mut interpreter = Interpreter::with_engine(Engine::Vm, Builtins::default());
+        for _ in 0..70_000 {
+            assert_eq!(Object::Integer(2), interpreter.eval("1 + 1").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_globals() {
+        for interpreter in interpreters() {
+            check_globals(interpreter);
+        }
+    }
+
+    fn check_globals(mut interpreter: Interpreter) {
+        let mut config = HashMap::new();
+        config.insert(String::from("retries"), 3);
+        config.insert(String::from("timeout"), 30);
+        interpreter.set_global("config", config);
+        interpreter.set_global("names", vec!["a", "b"]);
+        interpreter.set_global("verbose", true);
+
+        let value = interpreter
+            .eval(r#"if (verbose) { config["retries"] * len(names) }"#)
+            .unwrap();
+        assert_eq!(6, i64::from_monkey(&value).unwrap());
+
+        interpreter
+            .eval(r#"let result = {"total": config["timeout"] + 1, "ok": 1};"#)
+            .unwrap();
+        let result = interpreter.get_global("result").unwrap();
+        let result = HashMap::<String, i64>::from_monkey(&result).unwrap();
+        assert_eq!(Some(&31), result.get("total"));
+
+        let names = interpreter.get_global("names").unwrap();
+        assert_eq!(
+            vec![String::from("a"), String::from("b")],
+            Vec::<String>::from_monkey(&names).unwrap()
+        );
+        assert_eq!(None, interpreter.get_global("missing"));
+    }
+
+    #[test]
+    fn test_conversion_error() {
+        let error = Vec::<i64>::from_monkey(&Object::Array(vec![
+            Object::Integer(1),
+            Object::Boolean(true),
+        ]))
+        .unwrap_err();
+        assert_eq!("cannot convert BOOLEAN into INTEGER", error.to_string());
+    }
+}
@@ -1,5 +1,9 @@
-use crate::token::{lookup_ident, Token, TokenType};
+use std::borrow::Cow;
+
+use crate::token::{lookup_ident, Span, Token, TokenType};
 use log::debug;
+use thiserror::Error;
+use unicode_xid::UnicodeXID;
 
 #[test]
 fn test_next_token() {
@@ -14,7 +18,7 @@ fn test_next_token() {
     };
 
     let result = add(five, ten);
-    !-/*5;
+    !-/ *5;
     5 < 10 > 5;
 
     if (5 < 10) {
@@ -25,6 +29,10 @@ fn test_next_token() {
 
     10 == 10;
     10 != 9;
+    \"foobar\"
+    \"foo bar\"
+    [1, 2];
+    {\"foo\": \"bar\"}
     ",
     );
     let mut l = Lexer::new(&input);
@@ -68,7 +76,7 @@ fn test_next_token() {
         Token::new(TokenType::Ident, String::from("ten")),
         Token::new(TokenType::RParen, String::from(")")),
         Token::new(TokenType::Semicolon, String::from(";")),
-        // !-/*5;
+        // !-/ *5;
         Token::new(TokenType::Bang, String::from("!")),
         Token::new(TokenType::Minus, String::from("-")),
         Token::new(TokenType::Slash, String::from("/")),
@@ -114,141 +122,645 @@ fn test_next_token() {
         Token::new(TokenType::NotEq, String::from("!=")),
         Token::new(TokenType::Int, String::from("9")),
         Token::new(TokenType::Semicolon, String::from(";")),
+        // "foobar"
+        Token::new(TokenType::String, String::from("foobar")),
+        // "foo bar"
+        Token::new(TokenType::String, String::from("foo bar")),
+        // [1, 2];
+        Token::new(TokenType::LBracket, String::from("[")),
+        Token::new(TokenType::Int, String::from("1")),
+        Token::new(TokenType::Comma, String::from(",")),
+        Token::new(TokenType::Int, String::from("2")),
+        Token::new(TokenType::RBracket, String::from("]")),
+        Token::new(TokenType::Semicolon, String::from(";")),
+        // {"foo": "bar"}
+        Token::new(TokenType::LBrace, String::from("{")),
+        Token::new(TokenType::String, String::from("foo")),
+        Token::new(TokenType::Colon, String::from(":")),
+        Token::new(TokenType::String, String::from("bar")),
+        Token::new(TokenType::RBrace, String::from("}")),
         Token::new(TokenType::Eof, String::from("")),
     ];
 
     for expected in tests {
         let token = l.next_token();
         debug!("token: {:?}", token);
-        assert_eq!(expected, token);
+        assert_eq!(expected.token_type, token.token_type);
+        assert_eq!(expected.literal, token.literal);
+    }
+}
+
+#[test]
+fn test_token_span() {
+    let input = "let x = 10;\n  x == 5;";
+    let mut l = Lexer::new(input);
+    let tests = vec![
+        (TokenType::Let, 0, 3, 1, 1),
+        (TokenType::Ident, 4, 5, 1, 5),
+        (TokenType::Assign, 6, 7, 1, 7),
+        (TokenType::Int, 8, 10, 1, 9),
+        (TokenType::Semicolon, 10, 11, 1, 11),
+        (TokenType::Ident, 14, 15, 2, 3),
+        (TokenType::Eq, 16, 18, 2, 5),
+        (TokenType::Int, 19, 20, 2, 8),
+        (TokenType::Semicolon, 20, 21, 2, 9),
+        (TokenType::Eof, 21, 21, 2, 10),
+    ];
+
+    for (token_type, start, end, line, column) in tests {
+        let token = l.next_token();
+        assert_eq!(token_type, token.token_type);
+        assert_eq!(
+            Span {
+                start,
+                end,
+                line,
+                column
+            },
+            token.span
+        );
+    }
+}
+
+#[test]
+fn test_starting_at() {
+    // 2 行目から読み始めても、位置は入力全体でのものになる
+    let input = "let x = 1;\nx;";
+    let mut l = Lexer::starting_at(input, 11);
+    let tests = vec![
+        (TokenType::Ident, 11, 12, 2, 1),
+        (TokenType::Semicolon, 12, 13, 2, 2),
+        (TokenType::Eof, 13, 13, 2, 3),
+    ];
+
+    for (token_type, start, end, line, column) in tests {
+        let token = l.next_token();
+        assert_eq!(token_type, token.token_type);
+        assert_eq!(
+            Span {
+                start,
+                end,
+                line,
+                column
+            },
+            token.span
+        );
+    }
+}
+
+#[test]
+fn test_unicode_input() {
+    let input = "let 挨拶 = \"こんにちは\";\nlet café_1 = 挨拶 € x;";
+    let mut l = Lexer::new(input);
+    let tests = vec![
+        (TokenType::Let, "let", 0, 3, 1, 1),
+        (TokenType::Ident, "挨拶", 4, 10, 1, 5),
+        (TokenType::Assign, "=", 11, 12, 1, 8),
+        (TokenType::String, "こんにちは", 13, 30, 1, 10),
+        (TokenType::Semicolon, ";", 30, 31, 1, 17),
+        (TokenType::Let, "let", 32, 35, 2, 1),
+        (TokenType::Ident, "café_1", 36, 43, 2, 5),
+        (TokenType::Assign, "=", 44, 45, 2, 12),
+        (TokenType::Ident, "挨拶", 46, 52, 2, 14),
+        (TokenType::Illegal, "€", 53, 56, 2, 17),
+        (TokenType::Ident, "x", 57, 58, 2, 19),
+        (TokenType::Semicolon, ";", 58, 59, 2, 20),
+        (TokenType::Eof, "", 59, 59, 2, 21),
+    ];
+
+    for (token_type, literal, start, end, line, column) in tests {
+        let token = l.next_token();
+        assert_eq!(token_type, token.token_type);
+        assert_eq!(literal, token.literal);
+        assert_eq!(
+            Span {
+                start,
+                end,
+                line,
+                column
+            },
+            token.span
+        );
+    }
+    assert_eq!(
+        vec![LexError::IllegalCharacter('€')],
+        l.take_errors()
+            .into_iter()
+            .map(|(error, _)| error)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_comments() {
+    let input = "// 先頭のコメント
+let x = 1; // 行末のコメント
+/* ブロック
+   /* 入れ子 */ コメント */
+x / 2;
+/**/ x";
+    let mut l = Lexer::new(input);
+    let tests = vec![
+        (TokenType::Let, "let"),
+        (TokenType::Ident, "x"),
+        (TokenType::Assign, "="),
+        (TokenType::Int, "1"),
+        (TokenType::Semicolon, ";"),
+        (TokenType::Ident, "x"),
+        (TokenType::Slash, "/"),
+        (TokenType::Int, "2"),
+        (TokenType::Semicolon, ";"),
+        (TokenType::Ident, "x"),
+        (TokenType::Eof, ""),
+    ];
+
+    for (token_type, literal) in tests {
+        let token = l.next_token();
+        assert_eq!(token_type, token.token_type);
+        assert_eq!(literal, token.literal);
+    }
+    assert!(l.take_errors().is_empty());
+}
+
+#[test]
+fn test_emit_comments() {
+    let input = "let x = 1; // one\n/* a /* b */ c */ x";
+    let mut l = Lexer::new(input).with_comments();
+    let tests = vec![
+        (TokenType::Let, "let", 1, 1),
+        (TokenType::Ident, "x", 1, 5),
+        (TokenType::Assign, "=", 1, 7),
+        (TokenType::Int, "1", 1, 9),
+        (TokenType::Semicolon, ";", 1, 10),
+        (TokenType::Comment, "// one", 1, 12),
+        (TokenType::Comment, "/* a /* b */ c */", 2, 1),
+        (TokenType::Ident, "x", 2, 19),
+        (TokenType::Eof, "", 2, 20),
+    ];
+
+    for (token_type, literal, line, column) in tests {
+        let token = l.next_token();
+        assert_eq!(token_type, token.token_type);
+        assert_eq!(literal, token.literal);
+        assert_eq!((line, column), (token.span.line, token.span.column));
+    }
+}
+
+#[test]
+fn test_unterminated_comment() {
+    for input in ["x /* a /* b */", "x /* a"] {
+        for mut l in [Lexer::new(input), Lexer::new(input).with_comments()] {
+            assert_eq!(TokenType::Ident, l.next_token().token_type);
+            let mut token = l.next_token();
+            if token.token_type == TokenType::Illegal {
+                token = l.next_token();
+            }
+            assert_eq!(TokenType::Eof, token.token_type);
+
+            let errors = l.take_errors();
+            assert_eq!(1, errors.len());
+            assert_eq!(LexError::UnterminatedComment, errors[0].0);
+            assert_eq!((2, input.len()), (errors[0].1.start, errors[0].1.end));
+        }
+    }
+}
+
+#[test]
+fn test_string_escapes() {
+    let tests = vec![
+        (r#""a\nb""#, "a\nb"),
+        (r#""a\tb""#, "a\tb"),
+        (r#""say \"hi\"""#, "say \"hi\""),
+        (r#""back\\slash""#, "back\\slash"),
+        (r#""\u{41}\u{3042}""#, "A\u{3042}"),
+    ];
+
+    for (input, expected) in tests {
+        let mut l = Lexer::new(input);
+        let token = l.next_token();
+        assert_eq!(TokenType::String, token.token_type);
+        assert_eq!(expected, token.literal);
+        assert!(l.take_errors().is_empty());
     }
 }
 
+#[test]
+fn test_literals_borrow_input() {
+    let input = r#"let name = "plain" + "esc\n";"#;
+    let tokens: Vec<Token> = {
+        let mut l = Lexer::new(input);
+        std::iter::from_fn(|| Some(l.next_token()))
+            .take_while(|token| token.token_type != TokenType::Eof)
+            .collect()
+    };
+
+    // エスケープを展開した文字列だけが新しく確保される
+    let borrowed: Vec<bool> = tokens
+        .iter()
+        .map(|token| matches!(token.literal, Cow::Borrowed(_)))
+        .collect();
+    assert_eq!(vec![true, true, true, true, true, false, true], borrowed);
+    assert_eq!("esc\n", tokens[5].literal);
+}
+
+#[test]
+fn test_string_errors() {
+    let tests = vec![
+        ("let s = \"abc", LexError::UnterminatedString, 8, 12),
+        (
+            r#""a\qb""#,
+            LexError::InvalidEscape(String::from(r"\q")),
+            0,
+            6,
+        ),
+        (
+            r#""\u{110000}""#,
+            LexError::InvalidEscape(String::from(r"\u{110000}")),
+            0,
+            12,
+        ),
+        (
+            r#""\u41""#,
+            LexError::InvalidEscape(String::from(r"\u41")),
+            0,
+            6,
+        ),
+        (
+            r#""\u{4{1}""#,
+            LexError::InvalidEscape(String::from(r"\u{4{1}")),
+            0,
+            9,
+        ),
+        (
+            r#""\u{+41}""#,
+            LexError::InvalidEscape(String::from(r"\u{+41}")),
+            0,
+            9,
+        ),
+        (
+            r#""\u{}""#,
+            LexError::InvalidEscape(String::from(r"\u{}")),
+            0,
+            6,
+        ),
+        ("@", LexError::IllegalCharacter('@'), 0, 1),
+    ];
+
+    for (input, expected, start, end) in tests {
+        let mut l = Lexer::new(input);
+        let mut token = l.next_token();
+        while token.token_type != TokenType::Illegal && token.token_type != TokenType::Eof {
+            token = l.next_token();
+        }
+        assert_eq!(TokenType::Illegal, token.token_type, "{}", input);
+
+        let errors = l.take_errors();
+        assert_eq!(1, errors.len());
+        assert_eq!(expected, errors[0].0);
+        assert_eq!((start, end), (errors[0].1.start, errors[0].1.end));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum LexError {
+    #[error("illegal character {0:?}")]
+    IllegalCharacter(char),
+    #[error("unterminated string literal")]
+    UnterminatedString,
+    #[error("invalid escape sequence `{0}` in string literal")]
+    InvalidEscape(String),
+    #[error("unterminated block comment")]
+    UnterminatedComment,
+}
+
 pub struct Lexer<'a> {
     input: &'a str,
     position: usize,      // 入力における現在の位置(現在の文字を指し示す)
     read_position: usize, // これから読み込む位置(現在の文字の次)
-    ch: u8,               // 現在検査中の文字
+    ch: char,             // 現在検査中の文字
+    line: usize,          // 現在の文字がある行(1 始まり)
+    column: usize,        // 現在の文字の行内での位置(文字単位、1 始まり)
+    errors: Vec<(LexError, Span)>,
+    emit_comments: bool, // true ならコメントを読み飛ばさず Comment トークンとして返す
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::starting_at(input, 0)
+    }
+
+    // input の start バイト目 (文字の境界) から読み始める。Span は input 全体での位置になる
+    pub fn starting_at(input: &'a str, start: usize) -> Self {
+        let start = start.min(input.len());
         let mut lexer = Self {
             input,
-            position: 0,
-            read_position: 0,
-            ch: 0,
+            position: start,
+            read_position: start,
+            ch: '\0',
+            line: 1 + input[..start].matches('\n').count(),
+            column: 0,
+            errors: vec![],
+            emit_comments: false,
         };
         lexer.read_char();
 
-        return lexer;
+        lexer
+    }
+
+    // コメントも Comment トークンとして返すようにする(フォーマッタなどコメントを保持したい用途向け)
+    pub fn with_comments(mut self) -> Self {
+        self.emit_comments = true;
+        self
     }
 
     fn read_char(&mut self) {
-        debug!(
-            "(read_position, ch): ({}, {})",
-            self.read_position, self.ch as char
-        );
-        if self.read_position >= self.input.len() {
-            self.ch = 0;
-        } else {
-            self.ch = self.input.as_bytes()[self.read_position];
+        debug!("(read_position, ch): ({}, {})", self.read_position, self.ch);
+        if self.ch == '\n' {
+            self.line += 1;
+            self.column = 0;
         }
+        if self.read_position <= self.input.len() {
+            self.column += 1;
+        }
+        // position/read_position はバイト単位で、文字の長さだけ進める
         self.position = self.read_position;
-        self.read_position += 1;
+        match self.input[self.read_position.min(self.input.len())..]
+            .chars()
+            .next()
+        {
+            Some(ch) => {
+                self.ch = ch;
+                self.read_position += ch.len_utf8();
+            }
+            None => {
+                self.ch = '\0';
+                self.read_position += 1;
+            }
+        }
     }
 
-    fn peek_char(&self) -> u8 {
-        if self.read_position >= self.input.len() {
-            return 0;
-        } else {
-            return self.input.as_bytes()[self.read_position];
-        }
+    fn peek_char(&self) -> char {
+        self.input[self.read_position.min(self.input.len())..]
+            .chars()
+            .next()
+            .unwrap_or('\0')
     }
 
-    fn new_token(&self, token_type: TokenType, ch: u8) -> Token {
-        Token::new(token_type, String::from_utf8(vec![ch]).unwrap())
+    // 現在の 1 文字をリテラルとするトークンを作る
+    fn new_token(&self, token_type: TokenType) -> Token<'a> {
+        Token::new(token_type, &self.input[self.position..self.read_position])
     }
 
-    pub fn next_token(&mut self) -> Token {
+    pub fn next_token(&mut self) -> Token<'a> {
         self.skip_whitespace();
 
+        let mut span = self.start_span();
+        let result = self.read_token();
+        span.end = self.position.min(self.input.len());
+
+        match result {
+            Ok(mut tok) => {
+                tok.span = span;
+                tok
+            }
+            Err(error) => {
+                // エラーは記録しておき、読み飛ばした範囲を Illegal トークンとして返す
+                self.errors.push((error, span));
+                let literal = &self.input[span.start..span.end];
+                let mut tok = Token::new(TokenType::Illegal, literal);
+                tok.span = span;
+                tok
+            }
+        }
+    }
+
+    // 現在の文字から始まる Span を作る。end は読み進めた後に設定する
+    fn start_span(&self) -> Span {
+        let start = self.position.min(self.input.len());
+        Span {
+            start,
+            end: start,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    // これまでに発生した字句解析エラーを取り出す
+    pub fn take_errors(&mut self) -> Vec<(LexError, Span)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn read_token(&mut self) -> Result<Token<'a>, LexError> {
         let tok = match self.ch {
-            b'0'..=b'9' => return self.consume_number(),
+            '0'..='9' => return Ok(self.consume_number()),
+            '"' => self.read_string()?,
 
-            b'=' => match self.peek_char() {
-                b'=' => {
+            '=' => match self.peek_char() {
+                '=' => {
                     let position = self.position;
                     self.read_char();
                     let literal = &self.input[position..self.read_position];
-                    Token::new(TokenType::Eq, String::from(literal))
+                    Token::new(TokenType::Eq, literal)
                 }
-                _ => self.new_token(TokenType::Assign, self.ch),
+                _ => self.new_token(TokenType::Assign),
             },
-            b'+' => self.new_token(TokenType::Plus, self.ch),
-            b'-' => self.new_token(TokenType::Minus, self.ch),
-            b'!' => match self.peek_char() {
-                b'=' => {
+            '+' => self.new_token(TokenType::Plus),
+            '-' => self.new_token(TokenType::Minus),
+            '!' => match self.peek_char() {
+                '=' => {
                     let position = self.position;
                     self.read_char();
                     let literal = &self.input[position..self.read_position];
-                    Token::new(TokenType::NotEq, String::from(literal))
+                    Token::new(TokenType::NotEq, literal)
                 }
-                _ => self.new_token(TokenType::Bang, self.ch),
+                _ => self.new_token(TokenType::Bang),
             },
-            b'*' => self.new_token(TokenType::Asterisk, self.ch),
-            b'/' => self.new_token(TokenType::Slash, self.ch),
-            b'<' => self.new_token(TokenType::Lt, self.ch),
-            b'>' => self.new_token(TokenType::Gt, self.ch),
+            '*' => self.new_token(TokenType::Asterisk),
+            '/' if matches!(self.peek_char(), '/' | '*') => self.read_comment()?,
+            '/' => self.new_token(TokenType::Slash),
+            '<' => self.new_token(TokenType::Lt),
+            '>' => self.new_token(TokenType::Gt),
 
-            b',' => self.new_token(TokenType::Comma, self.ch),
-            b';' => self.new_token(TokenType::Semicolon, self.ch),
+            ',' => self.new_token(TokenType::Comma),
+            ':' => self.new_token(TokenType::Colon),
+            ';' => self.new_token(TokenType::Semicolon),
 
-            b'(' => self.new_token(TokenType::LParen, self.ch),
-            b')' => self.new_token(TokenType::RParen, self.ch),
-            b'{' => self.new_token(TokenType::LBrace, self.ch),
-            b'}' => self.new_token(TokenType::RBrace, self.ch),
+            '(' => self.new_token(TokenType::LParen),
+            ')' => self.new_token(TokenType::RParen),
+            '{' => self.new_token(TokenType::LBrace),
+            '}' => self.new_token(TokenType::RBrace),
+            '[' => self.new_token(TokenType::LBracket),
+            ']' => self.new_token(TokenType::RBracket),
 
-            0 => Token::new(TokenType::Eof, String::from("")),
+            '\0' => Token::new(TokenType::Eof, ""),
             _ => {
-                if self.is_letter(self.ch) {
+                if is_identifier_start(self.ch) {
                     let literal = self.read_identifier();
                     let token_type = lookup_ident(literal);
-                    return Token::new(token_type, String::from(literal));
+                    return Ok(Token::new(token_type, literal));
                 } else {
-                    self.new_token(TokenType::Illegal, self.ch)
+                    let ch = self.ch;
+                    self.read_char();
+                    return Err(LexError::IllegalCharacter(ch));
                 }
             }
         };
 
         self.read_char();
-        tok
+        Ok(tok)
     }
 
-    fn read_identifier(&mut self) -> &str {
+    // 開きの `"` から閉じの `"` の直前まで読み進める。閉じの `"` は呼び出し側で読み飛ばす
+    fn read_string(&mut self) -> Result<Token<'a>, LexError> {
+        let position = self.position + 1;
+        loop {
+            self.read_char();
+            if self.ch == '\\' {
+                self.read_char();
+            } else if self.ch == '"' {
+                break;
+            }
+            if self.ch == '\0' {
+                return Err(LexError::UnterminatedString);
+            }
+        }
+
+        match unescape(&self.input[position..self.position]) {
+            Ok(literal) => Ok(Token::new(TokenType::String, literal)),
+            Err(error) => {
+                // 閉じの `"` まで含めてエラーのトークンとする
+                self.read_char();
+                Err(error)
+            }
+        }
+    }
+
+    fn read_identifier(&mut self) -> &'a str {
         let position = self.position;
-        while self.is_letter(self.ch) {
+        while is_identifier_continue(self.ch) {
             self.read_char();
         }
         &self.input[position..self.position]
     }
 
-    fn is_letter(&self, ch: u8) -> bool {
-        matches!(ch, b'a'..=b'z' | b'A'..=b'Z' | b'_')
+    fn skip_whitespace(&mut self) {
+        loop {
+            while matches!(self.ch, ' ' | '\t' | '\n' | '\r') {
+                self.read_char();
+            }
+
+            if self.emit_comments || self.ch != '/' || !matches!(self.peek_char(), '/' | '*') {
+                return;
+            }
+
+            let mut span = self.start_span();
+            match self.read_comment() {
+                Ok(_) => self.read_char(),
+                Err(error) => {
+                    span.end = self.position.min(self.input.len());
+                    self.errors.push((error, span));
+                }
+            }
+        }
     }
 
-    fn skip_whitespace(&mut self) {
-        while matches!(self.ch, b' ' | b'\t' | b'\n') {
+    // `//` から行末まで、または `/*` から対応する `*/` まで読み進める(ブロックコメントは入れ子にできる)。
+    // 最後の文字は呼び出し側で読み飛ばす
+    fn read_comment(&mut self) -> Result<Token<'a>, LexError> {
+        let position = self.position;
+
+        if self.peek_char() == '/' {
+            while !matches!(self.peek_char(), '\n' | '\0') {
+                self.read_char();
+            }
+        } else {
             self.read_char();
+            let mut depth = 1;
+            while depth > 0 {
+                self.read_char();
+                match (self.ch, self.peek_char()) {
+                    ('\0', _) => return Err(LexError::UnterminatedComment),
+                    ('/', '*') => {
+                        self.read_char();
+                        depth += 1;
+                    }
+                    ('*', '/') => {
+                        self.read_char();
+                        depth -= 1;
+                    }
+                    _ => {}
+                }
+            }
         }
+
+        let literal = &self.input[position..self.read_position.min(self.input.len())];
+        Ok(Token::new(TokenType::Comment, literal))
     }
 
-    fn consume_number(&mut self) -> Token {
+    fn consume_number(&mut self) -> Token<'a> {
         let position = self.position;
-        while matches!(self.ch, b'0'..=b'9') {
+        while self.ch.is_ascii_digit() {
             self.read_char();
         }
         let literal = &self.input[position..self.position];
-        Token::new(TokenType::Int, String::from(literal))
+        Token::new(TokenType::Int, literal)
     }
 }
+
+fn is_identifier_start(ch: char) -> bool {
+    ch == '_' || UnicodeXID::is_xid_start(ch)
+}
+
+fn is_identifier_continue(ch: char) -> bool {
+    UnicodeXID::is_xid_continue(ch)
+}
+
+// エスケープが無ければソースをそのまま借用する
+fn unescape(raw: &str) -> Result<Cow<'_, str>, LexError> {
+    if !raw.contains('\\') {
+        return Ok(Cow::Borrowed(raw));
+    }
+
+    let mut value = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            value.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('"') => value.push('"'),
+            Some('\\') => value.push('\\'),
+            Some('u') => value.push(unescape_unicode(&mut chars)?),
+            Some(other) => return Err(LexError::InvalidEscape(format!("\\{}", other))),
+            None => return Err(LexError::InvalidEscape(String::from("\\"))),
+        }
+    }
+
+    Ok(Cow::Owned(value))
+}
+
+// `\u` に続く `{XXXX}` を読み、対応する文字を返す。`{` と `}` の間は 1〜6 桁の 16 進数に限る
+fn unescape_unicode(chars: &mut std::str::Chars) -> Result<char, LexError> {
+    let mut sequence = String::from("\\u");
+    for ch in chars.by_ref() {
+        sequence.push(ch);
+        if ch == '}' {
+            break;
+        }
+    }
+
+    sequence
+        .strip_prefix("\\u{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .filter(|digits| {
+            (1..=6).contains(&digits.len()) && digits.chars().all(|ch| ch.is_ascii_hexdigit())
+        })
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .and_then(char::from_u32)
+        .ok_or(LexError::InvalidEscape(sequence))
+}
@@ -1,4 +1,56 @@
 pub mod ast;
+pub mod builtins;
+pub mod code;
+pub mod compiler;
+pub mod diagnostics;
+pub mod environment;
+pub mod evaluator;
+pub mod interpreter;
 pub mod lexer;
+pub mod mkc;
+pub mod object;
 pub mod parser;
+pub mod repl;
+pub mod symbol_table;
 pub mod token;
+pub mod vm;
+
+pub use interpreter::{Engine, Error, FromMonkey, Interpreter, IntoMonkey, Value};
+
+use ast::Program;
+use lexer::Lexer;
+use parser::{ParseErrors, Parser};
+
+// 構文木は再帰で辿る (評価・コンパイル・解放) ので、深く入れ子になった式ではホストのスタックが溢れうる。
+// 再帰する前に残りが RED_ZONE を切っていたら、STACK_SEGMENT だけヒープにスタックを確保して続ける
+pub(crate) const RED_ZONE: usize = 64 * 1024;
+pub(crate) const STACK_SEGMENT: usize = 1024 * 1024;
+
+// ソースコードを構文解析して Program を返す。失敗した場合は見つかったすべてのエラーを返す
+pub fn parse(source: &str) -> Result<Program, ParseErrors> {
+    let lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer);
+    parser.parse_program().map_err(ParseErrors::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn test_parse() {
+        let program = parse("let x = 1 + 2; x * 3").unwrap();
+        assert_eq!("let x = (1 + 2);\n(x * 3)\n", program.to_string());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = parse("let x 5;\nlet = 1;").unwrap_err();
+        assert_eq!(2, errors.len());
+        assert_eq!(
+            "1:7: expected next token to be Assign, got Int instead\n\
+             2:5: expected next token to be Ident, got Assign instead",
+            errors.to_string()
+        );
+    }
+}
@@ -1,26 +1,309 @@
-use std::io::{self, Write};
+use std::{cell::RefCell, env, fs, path::PathBuf, process, rc::Rc};
 
-use monkey_interpreter_rust::{lexer::Lexer, token::TokenType};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use monkey_interpreter_rust::{
+    builtins::{Arity, Builtins},
+    compiler::{Bytecode, Compiler},
+    diagnostics::Diagnostic,
+    evaluator::RuntimeError,
+    mkc,
+    object::Object,
+    parse,
+    repl::{self, Mode, Repl},
+    vm::Vm,
+    Engine, Error, Interpreter,
+};
+
+thread_local! {
+    // 組み込み関数は関数ポインタなので、スクリプトに渡された引数はここから読む
+    static SCRIPT_ARGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+const USAGE: &str = "\
+usage: monkey [--mode=tokens|ast|eval] [--engine=vm|eval]
+       monkey [--engine=vm|eval] script.mk|script.mkc [args...]
+       monkey disasm script.mk|script.mkc
+       monkey compile script.mk [out.mkc]";
 
 fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let mut mode = None;
+    let mut engine = Engine::Eval;
+
+    // フラグはスクリプトのパスより前に置く。パス以降はすべてスクリプトへの引数
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(String::from(value))),
+            None => (flag.as_str(), None),
+        };
+        let result = match name {
+            "--mode" | "--engine" => match value.or_else(|| args.next()) {
+                Some(value) if name == "--mode" => value.parse().map(|value| mode = Some(value)),
+                Some(value) => value.parse().map(|value| engine = value),
+                None => Err(format!("missing value for {}", name)),
+            },
+            _ => Err(format!("unknown option `{}`", flag)),
+        };
+        if let Err(message) = result {
+            process::exit(usage_error(&message));
+        }
+    }
+
+    let args: Vec<String> = args.collect();
+    // --mode は REPL で何を表示するかの指定なので、スクリプトと一緒には使えない
+    if mode.is_some() && !args.is_empty() {
+        process::exit(usage_error("--mode only applies to the REPL"));
+    }
+    match args.split_first() {
+        Some((command, rest)) if command == "disasm" => process::exit(disassemble(rest)),
+        Some((command, rest)) if command == "compile" => process::exit(compile_file(rest)),
+        Some((path, script_args)) => process::exit(run_file(path, script_args, engine)),
+        None => start_repl(mode.unwrap_or(Mode::Eval), engine),
+    }
+}
+
+// スクリプトを実行し、プロセスの終了コードを返す
+fn run_file(path: &str, script_args: &[String], engine: Engine) -> i32 {
+    let builtins = script_builtins(script_args);
+    let source = match read_script(path) {
+        Some(Script::Source(source)) => source,
+        // コンパイル済みのプログラムは --engine に関係なく VM で実行する
+        Some(Script::Compiled(bytes)) => return run_compiled(path, &bytes, builtins),
+        None => return 1,
+    };
+
+    let mut interpreter = Interpreter::with_engine(engine, builtins);
+    match interpreter.eval(&source) {
+        Ok(_) => 0,
+        Err(error) => {
+            report(path, &source, error);
+            1
+        }
+    }
+}
+
+fn run_compiled(path: &str, bytes: &[u8], builtins: Builtins) -> i32 {
+    let bytecode = match mkc::decode(bytes, &builtins) {
+        Ok(bytecode) => bytecode,
+        Err(error) => {
+            eprintln!("error: could not load {}: {}", path, error);
+            return 1;
+        }
+    };
+
+    let mut vm = Vm::with_globals(bytecode, Rc::new(builtins), vec![]);
+    match vm.run() {
+        Ok(()) => 0,
+        // .mkc はソース上の位置を持たないので、disasm の出力と突き合わせられる命令の位置を示す
+        Err(error) => {
+            let location = match error.function {
+                Some(index) => format!("constant {}", index),
+                None => String::from("main"),
+            };
+            eprintln!(
+                "error: {}\n --> {}: in {} at {}",
+                error, path, location, error.offset
+            );
+            1
+        }
+    }
+}
+
+fn disassemble(args: &[String]) -> i32 {
+    let path = match args {
+        [path] => path,
+        _ => return usage_error("disasm takes exactly one file"),
+    };
+
+    let builtins = script_builtins(&[]);
+    let bytecode = match read_script(path) {
+        Some(Script::Source(source)) => compile_source(path, &source, &builtins),
+        Some(Script::Compiled(bytes)) => match mkc::decode(&bytes, &builtins) {
+            Ok(bytecode) => Some(bytecode),
+            Err(error) => {
+                eprintln!("error: could not load {}: {}", path, error);
+                None
+            }
+        },
+        None => None,
+    };
+
+    match bytecode {
+        Some(bytecode) => {
+            print!("{}", bytecode);
+            0
+        }
+        None => 1,
+    }
+}
+
+fn compile_file(args: &[String]) -> i32 {
+    let (path, output) = match args {
+        [path] => (path, PathBuf::from(path).with_extension("mkc")),
+        [path, output] => (path, PathBuf::from(output)),
+        _ => return usage_error("compile takes a script and an optional output file"),
+    };
+
+    let source = match read_script(path) {
+        Some(Script::Source(source)) => source,
+        Some(Script::Compiled(_)) => {
+            eprintln!("error: {} is already compiled", path);
+            return 1;
+        }
+        None => return 1,
+    };
+
+    let builtins = script_builtins(&[]);
+    let bytecode = match compile_source(path, &source, &builtins) {
+        Some(bytecode) => bytecode,
+        None => return 1,
+    };
+    let result = mkc::encode(&bytecode, &builtins)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| fs::write(&output, bytes).map_err(|error| error.to_string()));
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("error: could not write {}: {}", output.display(), error);
+            1
+        }
+    }
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("error: {}\n{}", message, USAGE);
+    2
+}
+
+// ソースのスクリプトか、mkc::MAGIC で始まるコンパイル済みのプログラム
+enum Script {
+    Source(String),
+    Compiled(Vec<u8>),
+}
+
+fn read_script(path: &str) -> Option<Script> {
+    let result = fs::read(path).map_err(|error| error.to_string());
+    let result = result.and_then(|bytes| {
+        if mkc::is_mkc(&bytes) {
+            Ok(Script::Compiled(bytes))
+        } else {
+            String::from_utf8(bytes)
+                .map(Script::Source)
+                .map_err(|error| error.to_string())
+        }
+    });
+
+    match result {
+        Ok(script) => Some(script),
+        Err(error) => {
+            eprintln!("error: could not read {}: {}", path, error);
+            None
+        }
+    }
+}
+
+// コンパイル済みのプログラムは組み込み関数を添字で参照するので、実行時と同じものを使う
+fn script_builtins(script_args: &[String]) -> Builtins {
+    SCRIPT_ARGS.with(|args| *args.borrow_mut() = script_args.to_vec());
+    let mut builtins = Builtins::default();
+    builtins.register("args", Arity::Exact(0), script_args_builtin);
+    builtins
+}
+
+fn compile_source(path: &str, source: &str, builtins: &Builtins) -> Option<Bytecode> {
+    let result = parse(source).map_err(Error::Parse).and_then(|program| {
+        let mut compiler = Compiler::with_builtins(builtins);
+        compiler.compile(&program).map_err(Error::Compile)?;
+        Ok(compiler.bytecode())
+    });
+
+    match result {
+        Ok(bytecode) => Some(bytecode),
+        Err(error) => {
+            report(path, source, error);
+            None
+        }
+    }
+}
+
+fn report(path: &str, source: &str, error: Error) {
+    match error {
+        Error::Parse(errors) => {
+            for error in errors.iter() {
+                let diagnostic = Diagnostic::from_parse_error(error, source);
+                eprint!("{}", diagnostic.render(path, source));
+            }
+        }
+        Error::Runtime { message, span } => {
+            eprint!("{}", Diagnostic::new(message, span).render(path, source));
+        }
+        Error::Compile(error) => {
+            let diagnostic = Diagnostic::new(error.to_string(), error.span());
+            eprint!("{}", diagnostic.render(path, source));
+        }
+        error => eprintln!("error: {}", error),
+    }
+}
+
+fn script_args_builtin(_: &[Object]) -> Result<Object, RuntimeError> {
+    let args = SCRIPT_ARGS.with(|args| args.borrow().clone());
+    Ok(Object::Array(
+        args.into_iter().map(Object::String).collect(),
+    ))
+}
+
+fn start_repl(mode: Mode, engine: Engine) {
     println!("Hello! This is the Monkey programming language!");
     println!("Feel free to type in commands");
+    println!(
+        "Switch what is shown with :mode tokens|ast|eval (now: {})",
+        mode
+    );
 
-    loop {
-        print!(">> ");
-        io::stdout().flush().unwrap();
-
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
+    let mut editor = DefaultEditor::new().expect("Failed to start line editor");
+    let history = history_path();
+    if let Some(path) = &history {
+        // 初回起動時は履歴ファイルが無いので、読み込みの失敗は無視する
+        let _ = editor.load_history(path);
+    }
 
-        let mut lexer = Lexer::new(&input);
-        while let tok = lexer.next_token() {
-            if tok.token_type == TokenType::Eof {
+    let mut repl = Repl::new(mode, engine);
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { ">> " } else { ".. " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+                if repl::is_incomplete(&input) {
+                    continue;
+                }
+                let _ = editor.add_history_entry(input.trim_end());
+                print!("{}", repl.handle_line(&input));
+                input.clear();
+            }
+            // Ctrl-C は入力途中の行を破棄する
+            Err(ReadlineError::Interrupted) => input.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("error: {}", error);
                 break;
             }
-            println!("{:?} ", tok);
         }
     }
+
+    if let Some(path) = &history {
+        if let Err(error) = editor.save_history(path) {
+            eprintln!(
+                "error: could not save history to {}: {}",
+                path.display(),
+                error
+            );
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".monkey_history"))
 }
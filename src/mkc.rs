@@ -0,0 +1,437 @@
+use std::{
+    collections::HashSet,
+    convert::{TryFrom, TryInto},
+    rc::Rc,
+};
+
+use thiserror::Error;
+
+use crate::{
+    builtins::Builtins,
+    code::{read_operands, Instructions, Opcode, SourceMap},
+    compiler::Bytecode,
+    object::{CompiledFunction, Object},
+};
+
+// コンパイル済みプログラムのファイル形式 (.mkc)。数値はすべてビッグエンディアン
+//
+//   magic "MKC\0", version: u16
+//   builtins: u32 個の名前 (u32 長 + UTF-8)
+//   instructions: u32 長 + バイト列
+//   constants: u32 個の定数 (u8 タグ + 値)
+//
+// 組み込み関数は添字で参照されるので、読み込み時に同じ並びであることを確かめる
+// ソースは含めないので、命令とソース上の位置の対応 (SourceMap) も保存しない
+// 読み込んだ命令列は、VM が範囲外を参照しないことを validate で確かめてから返す
+pub const MAGIC: &[u8; 4] = b"MKC\0";
+pub const VERSION: u16 = 1;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum MkcError {
+    #[error("not a compiled Monkey program")]
+    BadMagic,
+    #[error("unsupported format version {0}, expected {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("unexpected end of file")]
+    UnexpectedEof,
+    #[error("unknown constant tag {0}")]
+    UnknownConstantTag(u8),
+    #[error("string is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("constant of type {0} cannot be saved")]
+    UnsupportedConstant(&'static str),
+    #[error("invalid bytecode in {location} at {offset}: {reason}")]
+    InvalidBytecode {
+        location: String,
+        offset: usize,
+        reason: String,
+    },
+    #[error("compiled with different builtin functions: expected `{expected}` at #{index}, found `{actual}`")]
+    BuiltinMismatch {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+pub fn is_mkc(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(bytecode: &Bytecode, builtins: &Builtins) -> Result<Vec<u8>, MkcError> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_be_bytes());
+
+    let names: Vec<&str> = builtins
+        .iter()
+        .map(|builtin| builtin.name.as_str())
+        .collect();
+    write_u32(&mut out, names.len());
+    for name in names {
+        write_bytes(&mut out, name.as_bytes());
+    }
+
+    write_bytes(&mut out, &bytecode.instructions.0);
+
+    write_u32(&mut out, bytecode.constants.len());
+    for constant in bytecode.constants.iter() {
+        match constant {
+            Object::Integer(value) => {
+                out.push(TAG_INTEGER);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Object::String(value) => {
+                out.push(TAG_STRING);
+                write_bytes(&mut out, value.as_bytes());
+            }
+            Object::CompiledFunction(function) => {
+                out.push(TAG_FUNCTION);
+                write_u32(&mut out, function.num_locals);
+                write_u32(&mut out, function.num_parameters);
+                write_bytes(&mut out, &function.instructions.0);
+            }
+            constant => return Err(MkcError::UnsupportedConstant(constant.type_name())),
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8], builtins: &Builtins) -> Result<Bytecode, MkcError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(MkcError::BadMagic);
+    }
+    let version = u16::from_be_bytes(reader.take(2)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(MkcError::UnsupportedVersion(version));
+    }
+
+    // 実行側の組み込み関数が後ろに増えている分には問題ない
+    let num_builtins = reader.read_u32()?;
+    let mut expected = builtins.iter();
+    for index in 0..num_builtins {
+        let actual = reader.read_string()?;
+        match expected.next() {
+            Some(builtin) if builtin.name == actual => {}
+            builtin => {
+                return Err(MkcError::BuiltinMismatch {
+                    index,
+                    expected: actual,
+                    actual: builtin.map_or_else(String::new, |builtin| builtin.name.clone()),
+                })
+            }
+        }
+    }
+
+    let instructions = Instructions(reader.read_bytes()?.to_vec());
+
+    let count = reader.read_u32()?;
+    let mut constants = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let constant = match reader.take(1)?[0] {
+            TAG_INTEGER => Object::Integer(i64::from_be_bytes(reader.take(8)?.try_into().unwrap())),
+            TAG_STRING => Object::String(reader.read_string()?),
+            TAG_FUNCTION => {
+                let num_locals = reader.read_u32()?;
+                let num_parameters = reader.read_u32()?;
+                let instructions = Instructions(reader.read_bytes()?.to_vec());
+                Object::CompiledFunction(Rc::new(CompiledFunction {
+                    instructions,
+                    num_locals,
+                    num_parameters,
+                    source_map: SourceMap::default(),
+                }))
+            }
+            tag => return Err(MkcError::UnknownConstantTag(tag)),
+        };
+        constants.push(constant);
+    }
+
+    validate("main", &instructions, 0, &constants, num_builtins)?;
+    for (index, constant) in constants.iter().enumerate() {
+        if let Object::CompiledFunction(function) = constant {
+            let location = format!("constant {}", index);
+            let instructions = &function.instructions;
+            validate(
+                &location,
+                instructions,
+                function.num_locals,
+                &constants,
+                num_builtins,
+            )?;
+        }
+    }
+
+    Ok(Bytecode {
+        instructions,
+        constants: Rc::new(constants),
+        source_map: SourceMap::default(),
+    })
+}
+
+// オペコードとオペランドが揃っていること、定数・組み込み関数・ローカル変数の添字が範囲内であること、
+// ジャンプ先が命令の先頭か末尾であることを確かめる。スタックの深さは VM が実行時に確かめる
+fn validate(
+    location: &str,
+    instructions: &Instructions,
+    num_locals: usize,
+    constants: &[Object],
+    num_builtins: usize,
+) -> Result<(), MkcError> {
+    let bytes = &instructions.0;
+    let invalid = |offset, reason| MkcError::InvalidBytecode {
+        location: String::from(location),
+        offset,
+        reason,
+    };
+
+    let mut starts = HashSet::new();
+    let mut jumps = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let op = Opcode::try_from(bytes[offset])
+            .map_err(|byte| invalid(offset, format!("unknown opcode {}", byte)))?;
+        let definition = op.definition();
+        let width: usize = definition.operand_widths.iter().sum();
+        if offset + 1 + width > bytes.len() {
+            return Err(invalid(offset, format!("truncated {}", definition.name)));
+        }
+        let (operands, _) = read_operands(&definition, &bytes[offset + 1..]);
+
+        let out_of_range = match op {
+            Opcode::Constant => operands[0] >= constants.len(),
+            Opcode::Closure => !matches!(
+                constants.get(operands[0]),
+                Some(Object::CompiledFunction(_))
+            ),
+            Opcode::GetDeferredGlobal => {
+                !matches!(constants.get(operands[1]), Some(Object::String(_)))
+            }
+            Opcode::GetBuiltin => operands[0] >= num_builtins,
+            Opcode::GetLocal | Opcode::SetLocal => operands[0] >= num_locals,
+            Opcode::Jump | Opcode::JumpNotTruthy => {
+                jumps.push((offset, operands[0]));
+                false
+            }
+            _ => false,
+        };
+        if out_of_range {
+            let operands: Vec<String> = operands.iter().map(usize::to_string).collect();
+            return Err(invalid(
+                offset,
+                format!(
+                    "invalid operand {} of {}",
+                    operands.join(" "),
+                    definition.name
+                ),
+            ));
+        }
+
+        starts.insert(offset);
+        offset += 1 + width;
+    }
+
+    match jumps
+        .into_iter()
+        .find(|(_, target)| *target != bytes.len() && !starts.contains(target))
+    {
+        Some((offset, target)) => Err(invalid(offset, format!("invalid jump target {}", target))),
+        None => Ok(()),
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MkcError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(MkcError::UnexpectedEof)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<usize, MkcError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], MkcError> {
+        let len = self.read_u32()?;
+        self.take(len)
+    }
+
+    fn read_string(&mut self) -> Result<String, MkcError> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| MkcError::InvalidUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{decode, encode, is_mkc, MkcError, VERSION};
+    use crate::{
+        builtins::{Arity, Builtins},
+        code::{make, Instructions, Opcode, SourceMap},
+        compiler::{Bytecode, Compiler},
+        object::Object,
+        parse,
+        vm::{Vm, VmErrorKind},
+    };
+
+    fn compile(input: &str) -> Bytecode {
+        let program = parse(input).expect("failed to parse input");
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).expect("failed to compile");
+        compiler.bytecode()
+    }
+
+    fn instructions_of(constants: &[Object]) -> Vec<Option<&Instructions>> {
+        constants
+            .iter()
+            .map(|constant| match constant {
+                Object::CompiledFunction(function) => Some(&function.instructions),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytecode = compile(
+            r#"let greet = fn(name) { "こんにちは、" + name }; let n = -42; len(greet("monkey")) + n"#,
+        );
+        let builtins = Builtins::default();
+
+        let bytes = encode(&bytecode, &builtins).unwrap();
+        assert!(is_mkc(&bytes));
+
+        // 位置の対応は保存しないので、命令列と定数プールの命令列だけを比べる
+        let decoded = decode(&bytes, &builtins).unwrap();
+        assert_eq!(bytecode.instructions, decoded.instructions);
+        assert_eq!(
+            instructions_of(&bytecode.constants),
+            instructions_of(&decoded.constants)
+        );
+        assert!(decoded.source_map.0.is_empty());
+
+        let mut vm = Vm::new(decoded);
+        vm.run().unwrap();
+        assert_eq!(&Object::Integer(-30), vm.last_popped_stack_elem());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let builtins = Builtins::default();
+        let bytes = encode(&compile("1 + 2"), &builtins).unwrap();
+
+        assert_eq!(Err(MkcError::BadMagic), decode(b"let x = 1;", &builtins));
+
+        let mut future = bytes.clone();
+        future[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        assert_eq!(
+            Err(MkcError::UnsupportedVersion(VERSION + 1)),
+            decode(&future, &builtins)
+        );
+
+        assert_eq!(
+            Err(MkcError::UnexpectedEof),
+            decode(&bytes[..bytes.len() - 1], &builtins)
+        );
+
+        let mut other = Builtins::empty();
+        other.register("len", Arity::Exact(1), |_| Ok(Object::Null));
+        other.register("now", Arity::Exact(0), |_| Ok(Object::Null));
+        assert_eq!(
+            Err(MkcError::BuiltinMismatch {
+                index: 1,
+                expected: String::from("first"),
+                actual: String::from("now"),
+            }),
+            decode(&bytes, &other)
+        );
+    }
+
+    #[test]
+    fn test_invalid_bytecode() {
+        let builtins = Builtins::default();
+        let tests = vec![
+            (
+                vec![make(Opcode::Constant, &[5])],
+                0,
+                "invalid operand 5 of OpConstant",
+            ),
+            (vec![vec![Opcode::Pop as u8, 200]], 1, "unknown opcode 200"),
+            (
+                vec![vec![Opcode::Constant as u8, 0]],
+                0,
+                "truncated OpConstant",
+            ),
+            (
+                vec![make(Opcode::GetBuiltin, &[100])],
+                0,
+                "invalid operand 100 of OpGetBuiltin",
+            ),
+            (
+                vec![make(Opcode::GetLocal, &[0])],
+                0,
+                "invalid operand 0 of OpGetLocal",
+            ),
+            (
+                vec![make(Opcode::True, &[]), make(Opcode::JumpNotTruthy, &[2])],
+                1,
+                "invalid jump target 2",
+            ),
+        ];
+
+        for (instructions, offset, reason) in tests {
+            let bytecode = Bytecode {
+                instructions: Instructions(instructions.concat()),
+                constants: Rc::default(),
+                source_map: SourceMap::default(),
+            };
+            let bytes = encode(&bytecode, &builtins).unwrap();
+            assert_eq!(
+                Err(MkcError::InvalidBytecode {
+                    location: String::from("main"),
+                    offset,
+                    reason: String::from(reason),
+                }),
+                decode(&bytes, &builtins)
+            );
+        }
+
+        // スタックの深さは読み込み時には確かめず、実行時のエラーになる
+        let bytecode = Bytecode {
+            instructions: Instructions(make(Opcode::Add, &[])),
+            constants: Rc::default(),
+            source_map: SourceMap::default(),
+        };
+        let bytes = encode(&bytecode, &builtins).unwrap();
+        let mut vm = Vm::new(decode(&bytes, &builtins).unwrap());
+        assert_eq!(VmErrorKind::StackUnderflow, vm.run().unwrap_err().kind);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    ast::Statement,
+    builtins::Builtin,
+    code::{Instructions, SourceMap},
+    environment::Environment,
+    token::Span,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Object {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Null,
+    ReturnValue(Box<Object>),
+    Error {
+        message: String,
+        span: Span,
+    },
+    Function {
+        parameters: Vec<String>,
+        body: Box<Statement>,
+        env: Rc<RefCell<Environment>>,
+    },
+    Builtin(Builtin),
+    Array(Vec<Object>),
+    Hash(HashMap<HashKey, HashPair>),
+    CompiledFunction(Rc<CompiledFunction>),
+    Closure(Rc<Closure>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    pub num_locals: usize,
+    pub num_parameters: usize,
+    pub source_map: SourceMap,
+}
+
+// コンパイル済みの関数と、生成時に取り込んだ自由変数の値
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub free: Vec<Object>,
+}
+
+// ハッシュのキーとして使える値。元のオブジェクトは表示用に HashPair 側で保持する
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashPair {
+    pub key: Object,
+    pub value: Object,
+}
+
+pub trait Hashable {
+    fn hash_key(&self) -> Option<HashKey>;
+}
+
+impl Hashable for Object {
+    fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Object::Integer(value) => Some(HashKey::Integer(*value)),
+            Object::Boolean(value) => Some(HashKey::Boolean(*value)),
+            Object::String(value) => Some(HashKey::String(value.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "INTEGER",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Null => "NULL",
+            Object::ReturnValue(_) => "RETURN_VALUE",
+            Object::Error { .. } => "ERROR",
+            Object::Function { .. } => "FUNCTION",
+            Object::Builtin(_) => "BUILTIN",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::CompiledFunction(_) => "COMPILED_FUNCTION",
+            // 評価器の関数と同じ型として扱う
+            Object::Closure(_) => "FUNCTION",
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Object::Error { .. })
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Object::Null | Object::Boolean(false))
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Integer(value) => write!(f, "{}", value),
+            Object::Boolean(value) => write!(f, "{}", value),
+            Object::String(value) => write!(f, "{}", value),
+            Object::Null => write!(f, "null"),
+            Object::ReturnValue(value) => write!(f, "{}", value),
+            Object::Error { message, .. } => write!(f, "ERROR: {}", message),
+            Object::Function {
+                parameters, body, ..
+            } => write!(f, "fn({}) {}", parameters.join(", "), body),
+            Object::Builtin(builtin) => write!(f, "builtin function {}", builtin.name),
+            Object::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Object::Hash(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .values()
+                    .map(|pair| format!("{}: {}", pair.key, pair.value))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Object::CompiledFunction(function) => {
+                write!(
+                    f,
+                    "compiled function ({} bytes)",
+                    function.instructions.len()
+                )
+            }
+            Object::Closure(closure) => {
+                write!(f, "closure ({} bytes)", closure.function.instructions.len())
+            }
+        }
+    }
+}
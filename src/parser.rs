@@ -1,28 +1,33 @@
+use std::{fmt, mem};
+
 use thiserror::Error;
 
 use crate::{
-    ast::{Expression, Precedence, Program, Statement},
-    lexer::Lexer,
-    token::{Token, TokenType},
+    ast::{Expression, ExpressionKind, Precedence, Program, Statement, StatementKind},
+    lexer::{LexError, Lexer},
+    token::{Span, Token, TokenType},
+    RED_ZONE, STACK_SEGMENT,
 };
 
 #[cfg(test)]
 mod tests {
     use std::vec;
 
-    use super::Parser;
+    use super::{ParseError, Parser};
     use crate::{
-        ast::{Expression::*, Program, Statement},
+        ast::{Expression, ExpressionKind::*, Program, Statement, StatementKind},
         lexer::Lexer,
+        token::{Span, TokenType},
     };
 
-    fn check_parse_errors(p: &Parser) {
-        if p.errors.len() == 0 {
-            return;
-        }
+    fn check_parse_errors(result: Result<Program, Vec<ParseError>>) -> Program {
+        let errors = match result {
+            Ok(program) => return program,
+            Err(errors) => errors,
+        };
 
-        println!("parser has {} errors", p.errors.len());
-        for error in &p.errors {
+        println!("parser has {} errors", errors.len());
+        for error in &errors {
             println!("parser error: {}", error);
         }
         panic!("test failed");
@@ -32,11 +37,10 @@ mod tests {
     fn test_identifier_expression() {
         let input = "foobar";
 
-        let lexer = Lexer::new(&input);
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
 
-        let program = parser.parse_program();
-        check_parse_errors(&parser);
+        let program = check_parse_errors(parser.parse_program());
 
         if program.statements.len() != 1 {
             panic!(
@@ -47,14 +51,14 @@ mod tests {
 
         let statement = &program.statements[0];
 
-        let expression = match statement {
-            Statement::Expression(expression) => expression,
+        let expression = match &statement.kind {
+            StatementKind::Expression(expression) => expression,
             _ => {
                 panic!("program.statements[0] is not expression. got {}", statement);
             }
         };
 
-        let value = match expression {
+        let value = match &expression.kind {
             Identifier(value) => value,
             _ => panic!("expression is not Identifier. got {}", expression),
         };
@@ -65,11 +69,10 @@ mod tests {
     fn test_integer_literal_expression() {
         let input = "5;";
 
-        let lexer = Lexer::new(&input);
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
 
-        let program = parser.parse_program();
-        check_parse_errors(&parser);
+        let program = check_parse_errors(parser.parse_program());
 
         if program.statements.len() != 1 {
             panic!(
@@ -80,30 +83,488 @@ mod tests {
 
         let statement = &program.statements[0];
 
-        let expression = match statement {
-            Statement::Expression(expression) => expression,
+        let expression = match &statement.kind {
+            StatementKind::Expression(expression) => expression,
             _ => {
                 panic!("program.statements[0] is not expression. got {}", statement);
             }
         };
 
-        let value = match expression {
+        let value = match &expression.kind {
             IntegerLiteral(value) => *value,
-            _ => panic!("expression is not Identifier. got {}", expression),
+            _ => panic!("expression is not IntegerLiteral. got {}", expression),
         };
         assert_eq!(5, value);
     }
 
     #[test]
-    #[ignore = "not yet implemented"]
+    fn test_boolean_expression() {
+        let tests = vec![("true;", true), ("false;", false)];
+
+        for (input, expected) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            assert_eq!(1, program.statements.len());
+            assert_eq!(
+                StatementKind::Expression(Boolean(expected).into()),
+                program.statements[0].kind
+            );
+        }
+    }
+
+    #[test]
+    fn test_comments_are_ignored() {
+        let input = "let x = 1; // comment\n/* block */ x + 1";
+
+        for lexer in [Lexer::new(input), Lexer::new(input).with_comments()] {
+            let mut parser = Parser::new(lexer);
+            let program = check_parse_errors(parser.parse_program());
+
+            assert_eq!("let x = 1;\n(x + 1)\n", program.to_string());
+        }
+    }
+
+    #[test]
+    fn test_string_literal_expression() {
+        let input = r#""hello\tworld";"#;
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        assert_eq!(1, program.statements.len());
+        assert_eq!(
+            StatementKind::Expression(StringLiteral(String::from("hello\tworld")).into()),
+            program.statements[0].kind
+        );
+        assert_eq!("\"hello\\tworld\"", program.statements[0].to_string());
+    }
+
+    #[test]
+    fn test_string_literal_round_trip() {
+        // \r や NUL のような制御文字も、字句解析器が受け付けるエスケープで書き戻す
+        let input = r#""quote \" back\\slash \u{d}\u{0} あ\n""#;
+
+        let program = check_parse_errors(Parser::new(Lexer::new(input)).parse_program());
+
+        let printed = program.to_string();
+        let reparsed = check_parse_errors(Parser::new(Lexer::new(&printed)).parse_program());
+        assert_eq!(input, printed.trim_end());
+        assert_eq!(program, reparsed);
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let input = "let a = 1 @ 2;\nlet s = \"abc";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let errors = parser.parse_program().unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec!["illegal character '@'", "unterminated string literal"],
+            messages
+        );
+        assert_eq!((23, 27, 2, 9), span_tuple(errors[1].span()));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        // 上限までの入れ子は解析でき、それを超えるとスタックを溢れさせずにエラーになる
+        // 文の式自体で 1 段使うので、括弧は 999 個まで
+        let input = format!("{}1{}", "(".repeat(999), ")".repeat(999));
+        check_parse_errors(Parser::new(Lexer::new(&input)).parse_program());
+
+        let tests = vec![
+            format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000)),
+            format!("{}1", "-".repeat(200_000)),
+            format!(
+                "let f = fn(x) {{ x }}; {}1{}",
+                "f(".repeat(50_000),
+                ")".repeat(50_000)
+            ),
+        ];
+
+        for input in tests {
+            let mut parser = Parser::new(Lexer::new(&input));
+            let errors = parser.parse_program().unwrap_err();
+            let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            assert_eq!(vec!["expression nested too deeply"], messages);
+        }
+    }
+
+    #[test]
+    fn test_parsing_prefix_expressions() {
+        let tests = vec![("!5;", "!", 5), ("-15;", "-", 15)];
+
+        for (input, expected_operator, expected_value) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            assert_eq!(1, program.statements.len());
+            let expected = StatementKind::Expression(
+                Prefix {
+                    operator: String::from(expected_operator),
+                    right: Box::new(IntegerLiteral(expected_value).into()),
+                }
+                .into(),
+            );
+            assert_eq!(expected, program.statements[0].kind);
+        }
+    }
+
+    #[test]
+    fn test_parsing_infix_expressions() {
+        let tests = vec![
+            ("5 + 5;", 5, "+", 5),
+            ("5 - 5;", 5, "-", 5),
+            ("5 * 5;", 5, "*", 5),
+            ("5 / 5;", 5, "/", 5),
+            ("5 > 5;", 5, ">", 5),
+            ("5 < 5;", 5, "<", 5),
+            ("5 == 5;", 5, "==", 5),
+            ("5 != 5;", 5, "!=", 5),
+        ];
+
+        for (input, left_value, expected_operator, right_value) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            assert_eq!(1, program.statements.len());
+            let expected = StatementKind::Expression(
+                Infix {
+                    left: Box::new(IntegerLiteral(left_value).into()),
+                    operator: String::from(expected_operator),
+                    right: Box::new(IntegerLiteral(right_value).into()),
+                }
+                .into(),
+            );
+            assert_eq!(expected, program.statements[0].kind);
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence_parsing() {
+        let tests = vec![
+            ("-a * b", "((-a) * b)"),
+            ("!-a", "(!(-a))"),
+            ("a + b + c", "((a + b) + c)"),
+            ("a + b - c", "((a + b) - c)"),
+            ("a * b * c", "((a * b) * c)"),
+            ("a * b / c", "((a * b) / c)"),
+            ("a + b / c", "(a + (b / c))"),
+            ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+            ("3 + 4; -5 * 5", "(3 + 4)\n((-5) * 5)"),
+            ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+            ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+            (
+                "3 + 4 * 5 == 3 * 1 + 4 * 5",
+                "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))",
+            ),
+            ("true", "true"),
+            ("false", "false"),
+            ("3 > 5 == false", "((3 > 5) == false)"),
+            ("3 < 5 == true", "((3 < 5) == true)"),
+            ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+            ("(5 + 5) * 2", "((5 + 5) * 2)"),
+            ("2 / (5 + 5)", "(2 / (5 + 5))"),
+            ("-(5 + 5)", "(-(5 + 5))"),
+            ("!(true == true)", "(!(true == true))"),
+            ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+            (
+                "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+                "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
+            ),
+            (
+                "add(a + b + c * d / f + g)",
+                "add((((a + b) + ((c * d) / f)) + g))",
+            ),
+            (
+                "a * [1, 2, 3, 4][b * c] * d",
+                "((a * ([1, 2, 3, 4][(b * c)])) * d)",
+            ),
+            (
+                "add(a * b[2], b[1], 2 * [1, 2][1])",
+                "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            assert_eq!(expected, program.to_string().trim_end());
+        }
+    }
+
+    #[test]
+    fn test_if_expression() {
+        let input = "if (x < y) { x }";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        assert_eq!(1, program.statements.len());
+        let expected = StatementKind::Expression(
+            If {
+                condition: Box::new(
+                    Infix {
+                        left: Box::new(Identifier(String::from("x")).into()),
+                        operator: String::from("<"),
+                        right: Box::new(Identifier(String::from("y")).into()),
+                    }
+                    .into(),
+                ),
+                consequence: Box::new(
+                    StatementKind::Block(vec![StatementKind::Expression(
+                        Identifier(String::from("x")).into(),
+                    )
+                    .into()])
+                    .into(),
+                ),
+                alternative: None,
+            }
+            .into(),
+        );
+        assert_eq!(expected, program.statements[0].kind);
+    }
+
+    #[test]
+    fn test_if_else_expression() {
+        let input = "if (x < y) { x } else { let z = y; z }";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        assert_eq!(1, program.statements.len());
+        assert_eq!(
+            "if (x < y) { x } else { let z = y; z }",
+            program.statements[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_function_literal_parsing() {
+        let input = "fn(x, y) { x + y; }";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        assert_eq!(1, program.statements.len());
+        let expected = StatementKind::Expression(
+            Function {
+                parameters: vec![String::from("x"), String::from("y")],
+                body: Box::new(
+                    StatementKind::Block(vec![StatementKind::Expression(
+                        Infix {
+                            left: Box::new(Identifier(String::from("x")).into()),
+                            operator: String::from("+"),
+                            right: Box::new(Identifier(String::from("y")).into()),
+                        }
+                        .into(),
+                    )
+                    .into()])
+                    .into(),
+                ),
+            }
+            .into(),
+        );
+        assert_eq!(expected, program.statements[0].kind);
+    }
+
+    #[test]
+    fn test_function_parameter_parsing() {
+        let tests = vec![
+            ("fn() {};", vec![]),
+            ("fn(x) {};", vec!["x"]),
+            ("fn(x, y, z) {};", vec!["x", "y", "z"]),
+        ];
+
+        for (input, expected) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            match &program.statements[0].kind {
+                StatementKind::Expression(Expression {
+                    kind: Function { parameters, .. },
+                    ..
+                }) => assert_eq!(&expected, parameters),
+                statement => panic!("statement is not Function. got {}", statement),
+            }
+        }
+    }
+
+    #[test]
+    fn test_call_expression_parsing() {
+        let input = "add(1, 2 * 3, 4 + 5);";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        assert_eq!(1, program.statements.len());
+        match &program.statements[0].kind {
+            StatementKind::Expression(Expression {
+                kind:
+                    Call {
+                        function,
+                        arguments,
+                    },
+                ..
+            }) => {
+                assert_eq!("add", function.to_string());
+                let arguments: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+                assert_eq!(vec!["1", "(2 * 3)", "(4 + 5)"], arguments);
+            }
+            statement => panic!("statement is not Call. got {}", statement),
+        }
+    }
+
+    #[test]
+    fn test_parsing_array_literals() {
+        let tests = vec![
+            ("[]", vec![]),
+            ("[1, 2 * 2, 3 + 3]", vec!["1", "(2 * 2)", "(3 + 3)"]),
+        ];
+
+        for (input, expected) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            match &program.statements[0].kind {
+                StatementKind::Expression(Expression {
+                    kind: Array(elements),
+                    ..
+                }) => {
+                    let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                    assert_eq!(expected, elements);
+                }
+                statement => panic!("statement is not Array. got {}", statement),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parsing_hash_literals() {
+        let tests = vec![
+            ("{}", vec![]),
+            (
+                r#"{"one": 1, "two": 2, "three": 3}"#,
+                vec![(r#""one""#, "1"), (r#""two""#, "2"), (r#""three""#, "3")],
+            ),
+            (
+                r#"{"one": 0 + 1, "two": 10 - 8, "three": 15 / 5}"#,
+                vec![
+                    (r#""one""#, "(0 + 1)"),
+                    (r#""two""#, "(10 - 8)"),
+                    (r#""three""#, "(15 / 5)"),
+                ],
+            ),
+            ("{true: 1, 2: false}", vec![("true", "1"), ("2", "false")]),
+        ];
+
+        for (input, expected) in tests {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+
+            let program = check_parse_errors(parser.parse_program());
+
+            match &program.statements[0].kind {
+                StatementKind::Expression(Expression {
+                    kind: Hash(pairs), ..
+                }) => {
+                    let pairs: Vec<(String, String)> = pairs
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect();
+                    let expected: Vec<(String, String)> = expected
+                        .into_iter()
+                        .map(|(key, value)| (String::from(key), String::from(value)))
+                        .collect();
+                    assert_eq!(expected, pairs);
+                }
+                statement => panic!("statement is not Hash. got {}", statement),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parsing_hash_literal_errors() {
+        let input = r#"{"one" 1}"#;
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let errors = parser.parse_program().unwrap_err();
+        match &errors[0] {
+            ParseError::UnexpectedToken {
+                expected, actual, ..
+            } => {
+                assert_eq!(&TokenType::Colon, expected);
+                assert_eq!(&TokenType::Int, actual);
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_parsing_index_expressions() {
+        let input = "myArray[1 + 1]";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        let expected = StatementKind::Expression(
+            Index {
+                left: Box::new(Identifier(String::from("myArray")).into()),
+                index: Box::new(
+                    Infix {
+                        left: Box::new(IntegerLiteral(1).into()),
+                        operator: String::from("+"),
+                        right: Box::new(IntegerLiteral(1).into()),
+                    }
+                    .into(),
+                ),
+            }
+            .into(),
+        );
+        assert_eq!(expected, program.statements[0].kind);
+    }
+
+    #[test]
     fn test_string() {
         let program = Program {
-            statements: vec![Statement::Let {
-                name: Identifier(String::from("myVar")),
-                // value: Identifier(String::from("anotherVar"))
-            }],
+            statements: vec![StatementKind::Let {
+                name: Identifier(String::from("myVar")).into(),
+                value: Identifier(String::from("anotherValue")).into(),
+            }
+            .into()],
         };
-        assert_eq!("let myVar = anotherValue;", program.to_string());
+        assert_eq!("let myVar = anotherValue;\n", program.to_string());
     }
 
     #[test]
@@ -112,31 +573,53 @@ mod tests {
     let x = 5;
     let y = 10;
     let foobar = 838383;
+    let z = y;
+    let w = x + y;
     ";
 
-        let lexer = Lexer::new(&input);
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
 
-        let program = parser.parse_program();
-        check_parse_errors(&parser);
+        let program = check_parse_errors(parser.parse_program());
 
-        if program.statements.len() != 3 {
+        if program.statements.len() != 5 {
             panic!(
-                "program.statements does not contain 3 statements. got={}",
+                "program.statements does not contain 5 statements. got={}",
                 program.statements.len()
             );
         }
 
-        let expected = vec![
-            Statement::Let {
-                name: Identifier(String::from("x")),
-            },
-            Statement::Let {
-                name: Identifier(String::from("y")),
-            },
-            Statement::Let {
-                name: Identifier(String::from("foobar")),
-            },
+        let expected: Vec<Statement> = vec![
+            StatementKind::Let {
+                name: Identifier(String::from("x")).into(),
+                value: IntegerLiteral(5).into(),
+            }
+            .into(),
+            StatementKind::Let {
+                name: Identifier(String::from("y")).into(),
+                value: IntegerLiteral(10).into(),
+            }
+            .into(),
+            StatementKind::Let {
+                name: Identifier(String::from("foobar")).into(),
+                value: IntegerLiteral(838383).into(),
+            }
+            .into(),
+            StatementKind::Let {
+                name: Identifier(String::from("z")).into(),
+                value: Identifier(String::from("y")).into(),
+            }
+            .into(),
+            StatementKind::Let {
+                name: Identifier(String::from("w")).into(),
+                value: Infix {
+                    left: Box::new(Identifier(String::from("x")).into()),
+                    operator: String::from("+"),
+                    right: Box::new(Identifier(String::from("y")).into()),
+                }
+                .into(),
+            }
+            .into(),
         ];
         assert_eq!(expected, program.statements);
     }
@@ -150,11 +633,80 @@ mod tests {
     let 838383;
     ";
 
-        let lexer = Lexer::new(&input);
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
 
-        parser.parse_program();
-        check_parse_errors(&parser);
+        check_parse_errors(parser.parse_program());
+    }
+
+    #[test]
+    fn test_parse_errors_recovery() {
+        let input = "
+    let x 5;
+    let = 10;
+    let 838383;
+    ;
+    }
+    let y = add(1, 2;
+    let z = 3;
+    if (z) { let = 1; z }
+    let f = fn() { 1 + };
+    let w = 2;
+    ";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let errors = match parser.parse_program() {
+            Ok(program) => panic!("expected errors. got {}", program),
+            Err(errors) => errors,
+        };
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            vec![
+                "expected next token to be Assign, got Int instead",
+                "expected next token to be Ident, got Assign instead",
+                "expected next token to be Ident, got Int instead",
+                "no prefix parse function for Semicolon found",
+                "no prefix parse function for RBrace found",
+                "expected next token to be RParen, got Semicolon instead",
+                "expected next token to be Ident, got Assign instead",
+                "no prefix parse function for RBrace found",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_errors_accessor() {
+        let input = "let x 5; let y = 1;";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        assert!(parser.parse_program().is_err());
+
+        assert_eq!(1, parser.errors().len());
+        assert_eq!((6, 7, 1, 7), span_tuple(parser.errors()[0].span()));
+    }
+
+    #[test]
+    fn test_unterminated_block() {
+        let input = "fn(x) { x + 1";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        match parser.parse_program() {
+            Err(errors) => assert!(matches!(
+                errors[0],
+                ParseError::UnexpectedToken {
+                    expected: TokenType::RBrace,
+                    actual: TokenType::Eof,
+                    ..
+                }
+            )),
+            Ok(program) => panic!("expected errors. got {}", program),
+        }
     }
 
     #[test]
@@ -163,51 +715,221 @@ mod tests {
     return 5;
     return 10;
     return 838383;
+    return y;
+    return x + y;
     ";
 
-        let lexer = Lexer::new(&input);
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
 
-        let program = parser.parse_program();
-        check_parse_errors(&parser);
+        let program = check_parse_errors(parser.parse_program());
 
-        if program.statements.len() != 3 {
+        if program.statements.len() != 5 {
             panic!(
-                "program.statements does not contain 3 statements. got={}",
+                "program.statements does not contain 5 statements. got={}",
                 program.statements.len()
             );
         }
 
-        let expected = vec![Statement::Return, Statement::Return, Statement::Return];
+        let expected: Vec<Statement> = vec![
+            StatementKind::Return(IntegerLiteral(5).into()).into(),
+            StatementKind::Return(IntegerLiteral(10).into()).into(),
+            StatementKind::Return(IntegerLiteral(838383).into()).into(),
+            StatementKind::Return(Identifier(String::from("y")).into()).into(),
+            StatementKind::Return(
+                Infix {
+                    left: Box::new(Identifier(String::from("x")).into()),
+                    operator: String::from("+"),
+                    right: Box::new(Identifier(String::from("y")).into()),
+                }
+                .into(),
+            )
+            .into(),
+        ];
         assert_eq!(expected, program.statements);
     }
+
+    #[test]
+    fn test_expression_span() {
+        let input = "let x = 1;\nfoo(x, -2 * y) + 3;";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        let expression = match &program.statements[1].kind {
+            StatementKind::Expression(expression) => expression,
+            _ => panic!("statement is not Expression. got {}", program.statements[1]),
+        };
+        assert_eq!(
+            Span {
+                start: 11,
+                end: 29,
+                line: 2,
+                column: 1
+            },
+            expression.span
+        );
+
+        let call = match &expression.kind {
+            Infix { left, .. } => left,
+            _ => panic!("expression is not Infix. got {}", expression),
+        };
+        assert_eq!((11, 25, 2, 1), span_tuple(call.span));
+
+        let arguments = match &call.kind {
+            Call { arguments, .. } => arguments,
+            _ => panic!("expression is not Call. got {}", call),
+        };
+        assert_eq!((15, 16, 2, 5), span_tuple(arguments[0].span));
+        assert_eq!((18, 24, 2, 8), span_tuple(arguments[1].span));
+    }
+
+    #[test]
+    fn test_statement_span() {
+        let input = "let x = 1;\nreturn x;\nfn() { let y = 2; y }";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let program = check_parse_errors(parser.parse_program());
+
+        // 文の終わりのセミコロンは含めない
+        assert_eq!((0, 9, 1, 1), span_tuple(program.statements[0].span));
+        assert_eq!((11, 19, 2, 1), span_tuple(program.statements[1].span));
+        assert_eq!((21, 42, 3, 1), span_tuple(program.statements[2].span));
+
+        let body = match &program.statements[2].kind {
+            StatementKind::Expression(Expression {
+                kind: Function { body, .. },
+                ..
+            }) => body,
+            _ => panic!("statement is not Function. got {}", program.statements[2]),
+        };
+        assert_eq!((26, 42, 3, 6), span_tuple(body.span));
+        match &body.kind {
+            StatementKind::Block(statements) => {
+                assert_eq!((28, 37, 3, 8), span_tuple(statements[0].span));
+                assert_eq!((39, 40, 3, 19), span_tuple(statements[1].span));
+            }
+            _ => panic!("body is not Block. got {}", body),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let input = "let x = 1;\nlet y 2;";
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+
+        let errors = parser.parse_program().unwrap_err();
+        assert_eq!((17, 18, 2, 7), span_tuple(errors[0].span()));
+    }
+
+    fn span_tuple(span: Span) -> (usize, usize, usize, usize) {
+        (span.start, span.end, span.line, span.column)
+    }
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum ParseError {
     #[error("expected next token to be {expected:?}, got {actual:?} instead")]
     UnexpectedToken {
         expected: TokenType,
         actual: TokenType,
+        span: Span,
     },
-    #[error("could not parse {0} as integer")]
-    FailedToParseInteger(String),
+    #[error("could not parse {literal} as integer")]
+    FailedToParseInteger { literal: String, span: Span },
+    #[error("no prefix parse function for {token_type:?} found")]
+    NoPrefixParseFn { token_type: TokenType, span: Span },
+    #[error("{error}")]
+    Lex { error: LexError, span: Span },
+    #[error("expression nested too deeply")]
+    NestedTooDeeply { span: Span },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken { span, .. } => *span,
+            ParseError::FailedToParseInteger { span, .. } => *span,
+            ParseError::NoPrefixParseFn { span, .. } => *span,
+            ParseError::Lex { span, .. } => *span,
+            ParseError::NestedTooDeeply { span } => *span,
+        }
+    }
+}
+
+// 1 回の構文解析で見つかったすべてのエラー
+#[derive(Debug, Clone, Error)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl ParseErrors {
+    pub fn iter(&self) -> std::slice::Iter<'_, ParseError> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
+impl From<Vec<ParseError>> for ParseErrors {
+    fn from(errors: Vec<ParseError>) -> Self {
+        Self(errors)
+    }
+}
+
+impl IntoIterator for ParseErrors {
+    type Item = ParseError;
+    type IntoIter = std::vec::IntoIter<ParseError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let span = error.span();
+            write!(f, "{}:{}: {}", span.line, span.column, error)?;
+        }
+        Ok(())
+    }
+}
+
+// 式の入れ子の上限。括弧や前置演算子、関数の本体などが入れ子になるたびに再帰するので、
+// これより深いものは構文エラーにする
+const MAX_NESTING_DEPTH: usize = 1000;
+
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
-    current_token: Token,
-    peek_token: Token,
+    current_token: Token<'a>,
+    peek_token: Token<'a>,
     errors: Vec<ParseError>,
+    // 解析中の式の入れ子の深さ
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
         let mut parser = Self {
             lexer,
-            current_token: Token::new(TokenType::Eof, String::from("")),
-            peek_token: Token::new(TokenType::Eof, String::from("")),
+            current_token: Token::new(TokenType::Eof, ""),
+            peek_token: Token::new(TokenType::Eof, ""),
             errors: vec![],
+            depth: 0,
         };
 
         parser.next_token();
@@ -216,23 +938,54 @@ impl<'a> Parser<'a> {
         parser
     }
 
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    // トークンは複製せず、peek_token を current_token に移す
     fn next_token(&mut self) {
-        self.current_token = self.peek_token.clone();
-        self.peek_token = self.lexer.next_token();
+        self.current_token = mem::replace(&mut self.peek_token, self.lexer.next_token());
+        while self.peek_token.token_type == TokenType::Comment {
+            self.peek_token = self.lexer.next_token();
+        }
+        for (error, span) in self.lexer.take_errors() {
+            self.errors.push(ParseError::Lex { error, span });
+        }
     }
 
-    pub fn parse_program(&mut self) -> Program {
+    pub fn parse_program(&mut self) -> Result<Program, Vec<ParseError>> {
         let mut statements: Vec<Statement> = vec![];
 
         while self.current_token.token_type != TokenType::Eof {
             match self.parse_statement() {
                 Some(statement) => statements.push(statement),
-                None => {}
+                None => self.synchronize(),
             }
             self.next_token();
         }
 
-        Program { statements }
+        if self.errors.is_empty() {
+            Ok(Program { statements })
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+
+    // エラーが起きた文の残りを読み飛ばし、次の文の先頭(またはブロックの終わり)の直前まで進める。
+    // エラーの原因が `}` そのものだった場合は、ブロックを閉じられるようにそこで止まる
+    fn synchronize(&mut self) {
+        while !self.current_token_is(TokenType::Semicolon)
+            && !self.current_token_is(TokenType::RBrace)
+            && !self.current_token_is(TokenType::Eof)
+        {
+            if matches!(
+                self.peek_token.token_type,
+                TokenType::Let | TokenType::Return | TokenType::RBrace
+            ) {
+                return;
+            }
+            self.next_token();
+        }
     }
 
     fn parse_statement(&mut self) -> Option<Statement> {
@@ -244,44 +997,50 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_let_statement(&mut self) -> Option<Statement> {
+        let start = self.current_token.span;
         if !self.expect_peek(TokenType::Ident) {
             return None;
         }
 
-        let identifier = Expression::Identifier(self.current_token.literal.clone());
-        let statement = Statement::Let {
-            name: identifier,
-            // value,
-        };
+        let name = Expression::new(
+            ExpressionKind::Identifier(self.parse_identifier()),
+            self.current_token.span,
+        );
 
         if !self.expect_peek(TokenType::Assign) {
             return None;
         }
+        self.next_token();
+
+        let value = self.parse_expression(Precedence::Lowest)?;
 
-        // TODO: セミコロンに遭遇するまで式を読み飛ばしてしまっている
-        while !self.current_token_is(TokenType::Semicolon) {
+        if self.peek_token_is(&TokenType::Semicolon) {
             self.next_token();
         }
 
-        Some(statement)
+        let span = start.to(value.span);
+        Some(Statement::new(StatementKind::Let { name, value }, span))
     }
 
     fn parse_return_statement(&mut self) -> Option<Statement> {
-        let statement = Statement::Return;
+        let start = self.current_token.span;
         self.next_token();
 
-        // TODO: セミコロンに遭遇するまで式を読み飛ばしてしまっている
-        while !self.current_token_is(TokenType::Semicolon) {
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(&TokenType::Semicolon) {
             self.next_token();
         }
 
-        Some(statement)
+        let span = start.to(value.span);
+        Some(Statement::new(StatementKind::Return(value), span))
     }
 
     fn parse_expression_statement(&mut self) -> Option<Statement> {
-        let expression = self.parse_expression(Precedence::Lowest).unwrap();
+        let expression = self.parse_expression(Precedence::Lowest)?;
 
-        let statement = Statement::Expression(expression);
+        let span = expression.span;
+        let statement = Statement::new(StatementKind::Expression(expression), span);
 
         if self.peek_token_is(&TokenType::Semicolon) {
             self.next_token();
@@ -291,26 +1050,356 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            self.errors.push(ParseError::NestedTooDeeply {
+                span: self.current_token.span,
+            });
+            return None;
+        }
+
+        self.depth += 1;
+        let expression = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+            self.parse_nested_expression(precedence)
+        });
+        self.depth -= 1;
+        expression
+    }
+
+    fn parse_nested_expression(&mut self, precedence: Precedence) -> Option<Expression> {
         // トークンタイプにひもづけられた構文解析関数を呼び出す
-        let prefix = match self.current_token.token_type {
-            TokenType::Ident => Expression::Identifier(self.parse_identifier()),
-            TokenType::Int => Expression::IntegerLiteral(self.parse_integer_literal()),
-            _ => return None,
+        let span = self.current_token.span;
+        let mut left = match self.current_token.token_type {
+            TokenType::Ident => {
+                Expression::new(ExpressionKind::Identifier(self.parse_identifier()), span)
+            }
+            TokenType::Int => Expression::new(
+                ExpressionKind::IntegerLiteral(self.parse_integer_literal()),
+                span,
+            ),
+            TokenType::String => {
+                // エスケープを展開した文字列はすでに所有しているので、そのまま取り出す
+                let literal = mem::take(&mut self.current_token.literal);
+                Expression::new(ExpressionKind::StringLiteral(literal.into_owned()), span)
+            }
+            TokenType::True => Expression::new(ExpressionKind::Boolean(true), span),
+            TokenType::False => Expression::new(ExpressionKind::Boolean(false), span),
+            TokenType::Bang | TokenType::Minus => self.parse_prefix_expression()?,
+            TokenType::LParen => self.parse_grouped_expression()?,
+            TokenType::LBracket => {
+                let elements = self.parse_expression_list(TokenType::RBracket)?;
+                Expression::new(
+                    ExpressionKind::Array(elements),
+                    span.to(self.current_token.span),
+                )
+            }
+            TokenType::LBrace => self.parse_hash_literal()?,
+            TokenType::If => self.parse_if_expression()?,
+            TokenType::Function => self.parse_function_literal()?,
+            // 字句解析の段階でエラーとして記録済み
+            TokenType::Illegal => return None,
+            _ => {
+                self.errors.push(ParseError::NoPrefixParseFn {
+                    token_type: self.current_token.token_type.clone(),
+                    span,
+                });
+                return None;
+            }
+        };
+
+        // 次のトークンの優先順位の方が高い間は、左側の式を中置演算子の左辺として取り込んでいく
+        while !self.peek_token_is(&TokenType::Semicolon) && precedence < self.peek_precedence() {
+            match self.peek_token.token_type {
+                TokenType::Plus
+                | TokenType::Minus
+                | TokenType::Asterisk
+                | TokenType::Slash
+                | TokenType::Lt
+                | TokenType::Gt
+                | TokenType::Eq
+                | TokenType::NotEq => {
+                    self.next_token();
+                    left = self.parse_infix_expression(left)?;
+                }
+                TokenType::LParen => {
+                    self.next_token();
+                    left = self.parse_call_expression(left)?;
+                }
+                TokenType::LBracket => {
+                    self.next_token();
+                    left = self.parse_index_expression(left)?;
+                }
+                _ => return Some(left),
+            }
+        }
+
+        Some(left)
+    }
+
+    fn parse_prefix_expression(&mut self) -> Option<Expression> {
+        let start = self.current_token.span;
+        let operator = self.current_token.literal.to_string();
+        self.next_token();
+        let right = self.parse_expression(Precedence::Prefix)?;
+        let span = start.to(right.span);
+
+        Some(Expression::new(
+            ExpressionKind::Prefix {
+                operator,
+                right: Box::new(right),
+            },
+            span,
+        ))
+    }
+
+    fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
+        let operator = self.current_token.literal.to_string();
+        let precedence = self.current_precedence();
+        self.next_token();
+        let right = self.parse_expression(precedence)?;
+        let span = left.span.to(right.span);
+
+        Some(Expression::new(
+            ExpressionKind::Infix {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            },
+            span,
+        ))
+    }
+
+    fn parse_grouped_expression(&mut self) -> Option<Expression> {
+        let start = self.current_token.span;
+        self.next_token();
+
+        let mut expression = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenType::RParen) {
+            return None;
+        }
+        expression.span = start.to(self.current_token.span);
+
+        Some(expression)
+    }
+
+    fn parse_if_expression(&mut self) -> Option<Expression> {
+        let start = self.current_token.span;
+        if !self.expect_peek(TokenType::LParen) {
+            return None;
+        }
+        self.next_token();
+
+        let condition = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenType::RParen) {
+            return None;
+        }
+        if !self.expect_peek(TokenType::LBrace) {
+            return None;
+        }
+
+        let consequence = self.parse_block_statement()?;
+
+        let alternative = if self.peek_token_is(&TokenType::Else) {
+            self.next_token();
+            if !self.expect_peek(TokenType::LBrace) {
+                return None;
+            }
+            Some(Box::new(self.parse_block_statement()?))
+        } else {
+            None
         };
-        Some(prefix)
+
+        Some(Expression::new(
+            ExpressionKind::If {
+                condition: Box::new(condition),
+                consequence: Box::new(consequence),
+                alternative,
+            },
+            start.to(self.current_token.span),
+        ))
+    }
+
+    fn parse_function_literal(&mut self) -> Option<Expression> {
+        let start = self.current_token.span;
+        if !self.expect_peek(TokenType::LParen) {
+            return None;
+        }
+
+        let parameters = self.parse_function_parameters()?;
+
+        if !self.expect_peek(TokenType::LBrace) {
+            return None;
+        }
+
+        let body = self.parse_block_statement()?;
+
+        Some(Expression::new(
+            ExpressionKind::Function {
+                parameters,
+                body: Box::new(body),
+            },
+            start.to(self.current_token.span),
+        ))
+    }
+
+    fn parse_function_parameters(&mut self) -> Option<Vec<String>> {
+        let mut parameters = vec![];
+
+        if self.peek_token_is(&TokenType::RParen) {
+            self.next_token();
+            return Some(parameters);
+        }
+
+        if !self.expect_peek(TokenType::Ident) {
+            return None;
+        }
+        parameters.push(self.parse_identifier());
+
+        while self.peek_token_is(&TokenType::Comma) {
+            self.next_token();
+            if !self.expect_peek(TokenType::Ident) {
+                return None;
+            }
+            parameters.push(self.parse_identifier());
+        }
+
+        if !self.expect_peek(TokenType::RParen) {
+            return None;
+        }
+
+        Some(parameters)
+    }
+
+    fn parse_block_statement(&mut self) -> Option<Statement> {
+        let start = self.current_token.span;
+        let mut statements = vec![];
+        self.next_token();
+
+        while !self.current_token_is(TokenType::RBrace) && !self.current_token_is(TokenType::Eof) {
+            match self.parse_statement() {
+                Some(statement) => statements.push(statement),
+                None => {
+                    self.synchronize();
+                    if self.current_token_is(TokenType::RBrace) {
+                        break;
+                    }
+                }
+            }
+            self.next_token();
+        }
+
+        if self.current_token_is(TokenType::Eof) {
+            self.errors.push(ParseError::UnexpectedToken {
+                expected: TokenType::RBrace,
+                actual: TokenType::Eof,
+                span: self.current_token.span,
+            });
+            return None;
+        }
+
+        let span = start.to(self.current_token.span);
+        Some(Statement::new(StatementKind::Block(statements), span))
+    }
+
+    fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+        let arguments = self.parse_expression_list(TokenType::RParen)?;
+        let span = function.span.to(self.current_token.span);
+
+        Some(Expression::new(
+            ExpressionKind::Call {
+                function: Box::new(function),
+                arguments,
+            },
+            span,
+        ))
+    }
+
+    fn parse_hash_literal(&mut self) -> Option<Expression> {
+        let start = self.current_token.span;
+        let mut pairs = vec![];
+
+        while !self.peek_token_is(&TokenType::RBrace) {
+            self.next_token();
+            let key = self.parse_expression(Precedence::Lowest)?;
+
+            if !self.expect_peek(TokenType::Colon) {
+                return None;
+            }
+            self.next_token();
+            let value = self.parse_expression(Precedence::Lowest)?;
+            pairs.push((key, value));
+
+            if !self.peek_token_is(&TokenType::RBrace) && !self.expect_peek(TokenType::Comma) {
+                return None;
+            }
+        }
+
+        if !self.expect_peek(TokenType::RBrace) {
+            return None;
+        }
+
+        Some(Expression::new(
+            ExpressionKind::Hash(pairs),
+            start.to(self.current_token.span),
+        ))
+    }
+
+    fn parse_index_expression(&mut self, left: Expression) -> Option<Expression> {
+        self.next_token();
+        let index = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(TokenType::RBracket) {
+            return None;
+        }
+        let span = left.span.to(self.current_token.span);
+
+        Some(Expression::new(
+            ExpressionKind::Index {
+                left: Box::new(left),
+                index: Box::new(index),
+            },
+            span,
+        ))
+    }
+
+    // カンマ区切りの式の並びを end まで読む。呼び出し時は開き括弧が現在のトークン
+    fn parse_expression_list(&mut self, end: TokenType) -> Option<Vec<Expression>> {
+        let mut list = vec![];
+
+        if self.peek_token_is(&end) {
+            self.next_token();
+            return Some(list);
+        }
+
+        self.next_token();
+        list.push(self.parse_expression(Precedence::Lowest)?);
+
+        while self.peek_token_is(&TokenType::Comma) {
+            self.next_token();
+            self.next_token();
+            list.push(self.parse_expression(Precedence::Lowest)?);
+        }
+
+        if !self.expect_peek(end) {
+            return None;
+        }
+
+        Some(list)
     }
 
     fn parse_identifier(&self) -> String {
-        self.current_token.literal.clone()
+        self.current_token.literal.to_string()
     }
 
     fn parse_integer_literal(&mut self) -> i64 {
         match self.current_token.literal.parse::<i64>() {
             Ok(value) => value,
             Err(_) => {
-                self.errors.push(ParseError::FailedToParseInteger(
-                    self.current_token.literal.clone(),
-                ));
+                self.errors.push(ParseError::FailedToParseInteger {
+                    literal: self.current_token.literal.to_string(),
+                    span: self.current_token.span,
+                });
                 // FIXME: 適当な値を返すのではなく戻り値自体を Result にした方がよさそう
                 0
             }
@@ -328,17 +1417,38 @@ impl<'a> Parser<'a> {
     fn expect_peek(&mut self, t: TokenType) -> bool {
         if self.peek_token_is(&t) {
             self.next_token();
-            return true;
+            true
         } else {
             self.peek_error(t);
-            return false;
+            false
         }
     }
 
+    fn peek_precedence(&self) -> Precedence {
+        precedence_of(&self.peek_token.token_type)
+    }
+
+    fn current_precedence(&self) -> Precedence {
+        precedence_of(&self.current_token.token_type)
+    }
+
     fn peek_error(&mut self, t: TokenType) {
         self.errors.push(ParseError::UnexpectedToken {
             expected: t,
             actual: self.peek_token.token_type.clone(),
+            span: self.peek_token.span,
         });
     }
 }
+
+fn precedence_of(t: &TokenType) -> Precedence {
+    match t {
+        TokenType::Eq | TokenType::NotEq => Precedence::Equals,
+        TokenType::Lt | TokenType::Gt => Precedence::LessGreater,
+        TokenType::Plus | TokenType::Minus => Precedence::Sum,
+        TokenType::Asterisk | TokenType::Slash => Precedence::Product,
+        TokenType::LParen => Precedence::Call,
+        TokenType::LBracket => Precedence::Index,
+        _ => Precedence::Lowest,
+    }
+}
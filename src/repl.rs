@@ -0,0 +1,278 @@
+use std::{fmt, fmt::Write, str::FromStr};
+
+use crate::{
+    builtins::Builtins, diagnostics::Diagnostic, interpreter::Engine, lexer::Lexer, object::Object,
+    parse, parser::ParseErrors, token::TokenType, Error, Interpreter,
+};
+
+const REPL_FILE_NAME: &str = "<repl>";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Tokens,
+    Ast,
+    Eval,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokens" => Ok(Mode::Tokens),
+            "ast" => Ok(Mode::Ast),
+            "eval" => Ok(Mode::Eval),
+            _ => Err(format!(
+                "unknown mode `{}`, expected one of tokens, ast, eval",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Tokens => write!(f, "tokens"),
+            Mode::Ast => write!(f, "ast"),
+            Mode::Eval => write!(f, "eval"),
+        }
+    }
+}
+
+// 1 行ずつ入力を受け取り、表示する文字列を返す。eval モードの環境は行をまたいで保持される
+#[derive(Debug)]
+pub struct Repl {
+    mode: Mode,
+    interpreter: Interpreter,
+    // これまでに評価した入力をつなげたもの。エラーの位置はこの中での位置になる
+    source: String,
+}
+
+impl Repl {
+    pub fn new(mode: Mode, engine: Engine) -> Self {
+        Self {
+            mode,
+            interpreter: Interpreter::with_engine(engine, Builtins::default()),
+            source: String::new(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn handle_line(&mut self, line: &str) -> String {
+        if let Some(command) = line.trim().strip_prefix(':') {
+            return self.handle_command(command);
+        }
+
+        match self.mode {
+            Mode::Tokens => print_tokens(line),
+            Mode::Ast => print_ast(line),
+            Mode::Eval => self.print_eval(line),
+        }
+    }
+
+    fn handle_command(&mut self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("mode"), None, _) => format!("mode: {}\n", self.mode),
+            (Some("mode"), Some(mode), None) => match mode.parse() {
+                Ok(mode) => {
+                    self.mode = mode;
+                    format!("mode: {}\n", self.mode)
+                }
+                Err(message) => format!("error: {}\n", message),
+            },
+            _ => format!("error: unknown command `:{}`\n", command.trim()),
+        }
+    }
+
+    // 以前の入力で定義した関数の中で起きたエラーも、その入力の行を示せるように
+    // 入力をセッション全体のソースに追加してから評価する
+    fn print_eval(&mut self, line: &str) -> String {
+        if !self.source.is_empty() && !self.source.ends_with('\n') {
+            self.source.push('\n');
+        }
+        let start = self.source.len();
+        self.source.push_str(line);
+        let source = &self.source;
+
+        match self.interpreter.eval_from(source, start) {
+            // let 文などの値を持たない行では何も表示しない
+            Ok(Object::Null) => String::new(),
+            Ok(value) => format!("{}\n", value),
+            Err(Error::Parse(errors)) => render_parse_errors(&errors, source),
+            Err(Error::Runtime { message, span }) => {
+                Diagnostic::new(message, span).render(REPL_FILE_NAME, source)
+            }
+            Err(Error::Compile(error)) => {
+                Diagnostic::new(error.to_string(), error.span()).render(REPL_FILE_NAME, source)
+            }
+            Err(error) => format!("error: {}\n", error),
+        }
+    }
+}
+
+// 括弧が閉じていなければ入力の途中とみなす。文字列やコメントの中の括弧は字句解析で除かれる
+pub fn is_incomplete(source: &str) -> bool {
+    let mut lexer = Lexer::new(source);
+    let mut depth = 0;
+    loop {
+        match lexer.next_token().token_type {
+            TokenType::LParen | TokenType::LBrace | TokenType::LBracket => depth += 1,
+            TokenType::RParen | TokenType::RBrace | TokenType::RBracket => depth -= 1,
+            TokenType::Eof => return depth > 0,
+            _ => {}
+        }
+    }
+}
+
+fn print_tokens(line: &str) -> String {
+    let mut out = String::new();
+    let mut lexer = Lexer::new(line);
+    loop {
+        let token = lexer.next_token();
+        if token.token_type == TokenType::Eof {
+            break;
+        }
+        writeln!(out, "{:?}", token).unwrap();
+    }
+    out
+}
+
+fn print_ast(line: &str) -> String {
+    match parse(line) {
+        Ok(program) => program.to_string(),
+        Err(errors) => render_parse_errors(&errors, line),
+    }
+}
+
+fn render_parse_errors(errors: &ParseErrors, source: &str) -> String {
+    errors
+        .iter()
+        .map(|error| Diagnostic::from_parse_error(error, source).render(REPL_FILE_NAME, source))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_incomplete, Mode, Repl};
+    use crate::interpreter::Engine;
+
+    #[test]
+    fn test_switch_mode() {
+        let mut repl = Repl::new(Mode::Eval, Engine::Eval);
+
+        assert_eq!("mode: eval\n", repl.handle_line(":mode"));
+        assert_eq!("mode: ast\n", repl.handle_line(":mode ast"));
+        assert_eq!(Mode::Ast, repl.mode());
+        assert_eq!(
+            "error: unknown mode `bytes`, expected one of tokens, ast, eval\n",
+            repl.handle_line(":mode bytes")
+        );
+        assert_eq!(Mode::Ast, repl.mode());
+        assert_eq!(
+            "error: unknown command `:quit`\n",
+            repl.handle_line(":quit")
+        );
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut repl = Repl::new(Mode::Tokens, Engine::Eval);
+        assert_eq!(2, repl.handle_line("x;").lines().count());
+
+        repl.handle_line(":mode ast");
+        assert_eq!(
+            "let x = (1 + (2 * 3));\n",
+            repl.handle_line("let x = 1 + 2 * 3")
+        );
+
+        repl.handle_line(":mode eval");
+        assert_eq!("", repl.handle_line("let x = 1 + 2 * 3;"));
+        assert_eq!("14\n", repl.handle_line("x * 2"));
+    }
+
+    #[test]
+    fn test_vm_engine() {
+        let mut repl = Repl::new(Mode::Eval, Engine::Vm);
+
+        assert_eq!("", repl.handle_line("let add = fn(a, b) { a + b };"));
+        assert_eq!("3\n", repl.handle_line("add(1, 2)"));
+
+        let output = repl.handle_line("add(1, y)");
+        assert!(
+            output.starts_with("error: identifier not found: y\n --> <repl>:3:8\n"),
+            "{}",
+            output
+        );
+        // 実行時エラーも評価器と同じく位置を示す
+        assert_eq!(
+            "error: wrong number of arguments: want=2, got=1\n --> <repl>:4:1\n  |\n4 | add(1)\n  | ^^^^^^\n",
+            repl.handle_line("add(1)")
+        );
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        let tests = vec![
+            ("let x = 1;", false),
+            ("let f = fn(x) {", true),
+            ("let f = fn(x) {\n  x + 1\n", true),
+            ("let f = fn(x) {\n  x + 1\n};", false),
+            ("add(1,", true),
+            ("[1, 2", true),
+            (r#"let s = "{";"#, false),
+            ("let x = 1; // {", false),
+            // 閉じ括弧が多すぎる場合は評価させてエラーを表示する
+            ("}", false),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(expected, is_incomplete(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let mut repl = Repl::new(Mode::Eval, Engine::Eval);
+
+        let output = repl.handle_line("1 + true");
+        assert!(
+            output.starts_with("error: type mismatch: INTEGER + BOOLEAN\n --> <repl>:1:1\n"),
+            "{}",
+            output
+        );
+
+        let output = repl.handle_line("let x 1");
+        assert!(
+            output.contains("did you forget `=` after `let x`?"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_error_in_earlier_line() {
+        // 関数の中のエラーは、関数を定義した入力の行に対して表示する
+        for engine in [Engine::Eval, Engine::Vm] {
+            let mut repl = Repl::new(Mode::Eval, engine);
+            assert_eq!("", repl.handle_line("let f = fn(x) { x + true };\n"));
+            assert_eq!(
+                "\
+error: type mismatch: INTEGER + BOOLEAN
+ --> <repl>:1:17
+  |
+1 | let f = fn(x) { x + true };
+  |                 ^^^^^^^^
+",
+                repl.handle_line("\"aあああああ\"; f(1)\n"),
+                "{}",
+                engine
+            );
+            assert_eq!("2\n", repl.handle_line("1 + 1"));
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, mem};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolScope {
+    Global,
+    Local,
+    Builtin,
+    // 外側の関数のローカル変数をクロージャが取り込んだもの
+    Free,
+    // 関数リテラル自身の名前。再帰呼び出しのために使う
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    outer: Option<Box<SymbolTable>>,
+    store: HashMap<String, Symbol>,
+    num_definitions: usize,
+    pub free_symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn new_enclosed(outer: SymbolTable) -> Self {
+        Self {
+            outer: Some(Box::new(outer)),
+            ..Default::default()
+        }
+    }
+
+    // 関数のスコープを抜けるときに、外側のテーブルを取り出す
+    pub fn take_outer(&mut self) -> Option<SymbolTable> {
+        self.outer.take().map(|outer| *outer)
+    }
+
+    pub fn num_definitions(&self) -> usize {
+        self.num_definitions
+    }
+
+    // このテーブル自身に定義された名前だけを探す
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.store.get(name)
+    }
+
+    pub fn define(&mut self, name: &str) -> Symbol {
+        // 最上位で同じ名前を let し直した場合は、評価器と同じく同じ変数として扱う
+        if self.outer.is_none() {
+            if let Some(symbol) = self.store.get(name) {
+                if symbol.scope == SymbolScope::Global {
+                    return symbol.clone();
+                }
+            }
+        }
+
+        let scope = if self.outer.is_none() {
+            SymbolScope::Global
+        } else {
+            SymbolScope::Local
+        };
+        let symbol = Symbol {
+            name: String::from(name),
+            scope,
+            index: self.num_definitions,
+        };
+        self.store.insert(String::from(name), symbol.clone());
+        self.num_definitions += 1;
+        symbol
+    }
+
+    // 関数の本体から参照された未定義の名前を、最上位のテーブルに大域変数として予約する
+    pub fn declare_global(&mut self, name: &str) -> Symbol {
+        match self.outer.as_mut() {
+            Some(outer) => outer.declare_global(name),
+            None => self.define(name),
+        }
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> Symbol {
+        self.insert(name, SymbolScope::Builtin, index)
+    }
+
+    pub fn define_function_name(&mut self, name: &str) -> Symbol {
+        self.insert(name, SymbolScope::Function, 0)
+    }
+
+    fn define_free(&mut self, original: Symbol) -> Symbol {
+        let index = self.free_symbols.len();
+        let name = original.name.clone();
+        self.free_symbols.push(original);
+        self.insert(&name, SymbolScope::Free, index)
+    }
+
+    fn insert(&mut self, name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        let symbol = Symbol {
+            name: String::from(name),
+            scope,
+            index,
+        };
+        self.store.insert(String::from(name), symbol.clone());
+        symbol
+    }
+
+    // 外側の関数のローカル変数が見つかった場合は、自由変数としてこのテーブルに登録し直す
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            return Some(symbol.clone());
+        }
+
+        let symbol = self.outer.as_mut()?.resolve(name)?;
+        match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => Some(symbol),
+            _ => Some(self.define_free(symbol)),
+        }
+    }
+}
+
+// 関数のスコープに入る。戻るときは leave で外側のテーブルを戻す
+pub fn enter(table: &mut SymbolTable) {
+    let outer = mem::take(table);
+    *table = SymbolTable::new_enclosed(outer);
+}
+
+pub fn leave(table: &mut SymbolTable) -> SymbolTable {
+    let outer = table.take_outer().expect("symbol table has no outer scope");
+    mem::replace(table, outer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Symbol, SymbolScope, SymbolTable};
+
+    fn symbol(name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        Symbol {
+            name: String::from(name),
+            scope,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_define_and_resolve() {
+        let mut global = SymbolTable::new();
+        assert_eq!(symbol("a", SymbolScope::Global, 0), global.define("a"));
+        assert_eq!(symbol("b", SymbolScope::Global, 1), global.define("b"));
+
+        let mut first_local = SymbolTable::new_enclosed(global);
+        assert_eq!(symbol("c", SymbolScope::Local, 0), first_local.define("c"));
+        assert_eq!(symbol("d", SymbolScope::Local, 1), first_local.define("d"));
+
+        let mut second_local = SymbolTable::new_enclosed(first_local);
+        assert_eq!(symbol("e", SymbolScope::Local, 0), second_local.define("e"));
+
+        let expected = vec![
+            symbol("a", SymbolScope::Global, 0),
+            symbol("b", SymbolScope::Global, 1),
+            symbol("c", SymbolScope::Free, 0),
+            symbol("d", SymbolScope::Free, 1),
+            symbol("e", SymbolScope::Local, 0),
+        ];
+        for expected in expected {
+            assert_eq!(Some(expected.clone()), second_local.resolve(&expected.name));
+        }
+        assert_eq!(
+            vec![
+                symbol("c", SymbolScope::Local, 0),
+                symbol("d", SymbolScope::Local, 1)
+            ],
+            second_local.free_symbols
+        );
+        assert_eq!(None, second_local.resolve("f"));
+    }
+
+    #[test]
+    fn test_redefine_and_declare_globals() {
+        let mut global = SymbolTable::new();
+        assert_eq!(symbol("a", SymbolScope::Global, 0), global.define("a"));
+        assert_eq!(symbol("a", SymbolScope::Global, 0), global.define("a"));
+
+        let mut local = SymbolTable::new_enclosed(global);
+        assert_eq!(symbol("x", SymbolScope::Local, 0), local.define("x"));
+        assert_eq!(symbol("x", SymbolScope::Local, 1), local.define("x"));
+        assert_eq!(
+            symbol("b", SymbolScope::Global, 1),
+            local.declare_global("b")
+        );
+
+        let mut global = local.take_outer().unwrap();
+        assert_eq!(symbol("b", SymbolScope::Global, 1), global.define("b"));
+    }
+
+    #[test]
+    fn test_resolve_builtins() {
+        let mut global = SymbolTable::new();
+        global.define_builtin(0, "len");
+        global.define_builtin(1, "puts");
+
+        let mut local = SymbolTable::new_enclosed(SymbolTable::new_enclosed(global));
+
+        assert_eq!(
+            Some(symbol("puts", SymbolScope::Builtin, 1)),
+            local.resolve("puts")
+        );
+        assert!(local.free_symbols.is_empty());
+    }
+
+    #[test]
+    fn test_shadowing_function_name() {
+        let mut global = SymbolTable::new();
+        global.define_function_name("a");
+        global.define("a");
+
+        assert_eq!(
+            Some(symbol("a", SymbolScope::Global, 0)),
+            global.resolve("a")
+        );
+    }
+}
@@ -1,10 +1,14 @@
+use std::borrow::Cow;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     Illegal,
     Eof,
+    Comment, // Lexer::with_comments のときだけ返される
     // 識別子 + リテラル
-    Ident, // add, foobar, x, y, ...
-    Int,   // 123456
+    Ident,  // add, foobar, x, y, ...
+    Int,    // 123456
+    String, // "foobar"
 
     // 演算子
     Assign,
@@ -21,12 +25,15 @@ pub enum TokenType {
 
     // デリミタ
     Comma,
+    Colon,
     Semicolon,
 
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
 
     // キーワード
     Function,
@@ -38,10 +45,33 @@ pub enum TokenType {
     Return,
 }
 
+// ソース上の位置。start/end はバイトオフセット、line/column は 1 始まり
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // self の開始位置から other の終了位置までを覆う Span を返す
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+// literal はソースの該当部分を借用する。エスケープを含む文字列リテラルだけは展開後の値を所有する
 #[derive(Debug, Clone, PartialEq)]
-pub struct Token {
+pub struct Token<'a> {
     pub token_type: TokenType,
-    pub literal: String,
+    pub literal: Cow<'a, str>,
+    pub span: Span,
 }
 
 pub fn lookup_ident(ident: &str) -> TokenType {
@@ -57,11 +87,12 @@ pub fn lookup_ident(ident: &str) -> TokenType {
     }
 }
 
-impl Token {
-    pub fn new(token_type: TokenType, literal: String) -> Self {
+impl<'a> Token<'a> {
+    pub fn new(token_type: TokenType, literal: impl Into<Cow<'a, str>>) -> Self {
         Self {
             token_type,
-            literal,
+            literal: literal.into(),
+            span: Span::default(),
         }
     }
 }
@@ -0,0 +1,657 @@
+use std::{collections::HashMap, convert::TryFrom, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{
+    builtins::Builtins,
+    code::{read_u16, Opcode},
+    compiler::Bytecode,
+    evaluator::{eval_index_expression, eval_infix_expression, eval_prefix_expression},
+    object::{Closure, CompiledFunction, HashPair, Hashable, Object},
+    token::Span,
+};
+
+const STACK_SIZE: usize = 2048;
+const MAX_FRAMES: usize = 1024;
+
+// 実行時エラーと、エラーになった命令を生成した式の位置
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{kind}")]
+pub struct VmError {
+    pub kind: VmErrorKind,
+    // 位置の対応を持たないバイトコード (.mkc) では None
+    pub span: Option<Span>,
+    // エラーになった命令の関数と、その命令列の中での位置。関数は定数プールの添字で、main では None
+    pub function: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum VmErrorKind {
+    // 評価器と同じ文言の実行時エラー
+    #[error("{0}")]
+    Runtime(String),
+    #[error("stack overflow")]
+    StackOverflow,
+    #[error("invalid opcode {0}")]
+    InvalidOpcode(u8),
+    // 以下は壊れたバイトコードを実行した場合のエラー
+    #[error("stack underflow")]
+    StackUnderflow,
+    #[error("truncated instruction {0}")]
+    TruncatedInstruction(&'static str),
+    #[error("invalid operand {operand} of {opcode}")]
+    InvalidOperand {
+        opcode: &'static str,
+        operand: usize,
+    },
+}
+
+impl From<String> for VmErrorKind {
+    fn from(message: String) -> Self {
+        VmErrorKind::Runtime(message)
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // 実行中の命令の先頭の位置
+    instruction_start: usize,
+    // 呼び出された関数のローカル変数が始まるスタック上の位置
+    base_pointer: usize,
+}
+
+#[derive(Debug)]
+pub struct Vm {
+    constants: Rc<Vec<Object>>,
+    stack: Vec<Object>,
+    // まだ let されていない大域変数は None
+    globals: Vec<Option<Object>>,
+    frames: Vec<Frame>,
+    builtins: Rc<Builtins>,
+    last_popped: Object,
+}
+
+impl Vm {
+    pub fn new(bytecode: Bytecode) -> Self {
+        Self::with_globals(bytecode, Rc::new(Builtins::default()), vec![])
+    }
+
+    // REPL のように複数回実行する場合は、前回の globals を引き継ぐ
+    pub fn with_globals(
+        bytecode: Bytecode,
+        builtins: Rc<Builtins>,
+        globals: Vec<Option<Object>>,
+    ) -> Self {
+        let main_function = CompiledFunction {
+            instructions: bytecode.instructions,
+            num_locals: 0,
+            num_parameters: 0,
+            source_map: bytecode.source_map,
+        };
+        let main_closure = Closure {
+            function: Rc::new(main_function),
+            free: vec![],
+        };
+
+        Self {
+            constants: bytecode.constants,
+            stack: Vec::with_capacity(STACK_SIZE),
+            globals,
+            frames: vec![Frame {
+                closure: Rc::new(main_closure),
+                ip: 0,
+                instruction_start: 0,
+                base_pointer: 0,
+            }],
+            builtins,
+            last_popped: Object::Null,
+        }
+    }
+
+    // 最後に評価した文の値。評価器の eval の戻り値に相当する
+    pub fn last_popped_stack_elem(&self) -> &Object {
+        &self.last_popped
+    }
+
+    pub fn into_globals(self) -> Vec<Option<Object>> {
+        self.globals
+    }
+
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.execute().map_err(|kind| VmError {
+            kind,
+            span: self.current_span(),
+            function: self.current_function_index(),
+            offset: self.current_frame().instruction_start,
+        })
+    }
+
+    fn execute(&mut self) -> Result<(), VmErrorKind> {
+        while let Some(byte) = self.fetch() {
+            let op = Opcode::try_from(byte).map_err(VmErrorKind::InvalidOpcode)?;
+
+            match op {
+                Opcode::Constant => {
+                    let index = self.read_u16(op)?;
+                    self.push(self.constant(op, index)?.clone())?;
+                }
+                Opcode::Pop => {
+                    self.last_popped = self.pop()?;
+                }
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Equal
+                | Opcode::NotEqual
+                | Opcode::GreaterThan
+                | Opcode::LessThan => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    let result = eval_infix_expression(infix_operator(op), left, right)?;
+                    self.push(result)?;
+                }
+                Opcode::True => self.push(Object::Boolean(true))?,
+                Opcode::False => self.push(Object::Boolean(false))?,
+                Opcode::Null => self.push(Object::Null)?,
+                Opcode::Minus | Opcode::Bang => {
+                    let operator = if op == Opcode::Minus { "-" } else { "!" };
+                    let right = self.pop()?;
+                    self.push(eval_prefix_expression(operator, right)?)?;
+                }
+                Opcode::JumpNotTruthy => {
+                    let position = self.read_u16(op)?;
+                    if !self.pop()?.is_truthy() {
+                        self.current_frame_mut().ip = position;
+                    }
+                }
+                Opcode::Jump => {
+                    let position = self.read_u16(op)?;
+                    self.current_frame_mut().ip = position;
+                }
+                Opcode::SetGlobal => {
+                    let index = self.read_u16(op)?;
+                    if index >= self.globals.len() {
+                        self.globals.resize(index + 1, None);
+                    }
+                    self.globals[index] = Some(self.pop()?);
+                    // 評価器と同じく let 文の値は null とする
+                    self.last_popped = Object::Null;
+                }
+                Opcode::GetGlobal => {
+                    let index = self.read_u16(op)?;
+                    let value = self.globals.get(index).cloned().flatten();
+                    self.push(value.unwrap_or(Object::Null))?;
+                }
+                Opcode::GetDeferredGlobal => {
+                    let index = self.read_u16(op)?;
+                    let name = self.read_u16(op)?;
+                    match self.globals.get(index).cloned().flatten() {
+                        Some(value) => self.push(value)?,
+                        None => {
+                            return Err(VmErrorKind::Runtime(format!(
+                                "identifier not found: {}",
+                                self.constant(op, name)?
+                            )))
+                        }
+                    }
+                }
+                Opcode::SetLocal => {
+                    let index = self.read_u8(op)?;
+                    let value = self.pop()?;
+                    let base_pointer = self.current_frame().base_pointer;
+                    *self
+                        .stack
+                        .get_mut(base_pointer + index)
+                        .ok_or_else(|| invalid_operand(op, index))? = value;
+                }
+                Opcode::GetLocal => {
+                    let index = self.read_u8(op)?;
+                    let base_pointer = self.current_frame().base_pointer;
+                    let value = self
+                        .stack
+                        .get(base_pointer + index)
+                        .cloned()
+                        .ok_or_else(|| invalid_operand(op, index))?;
+                    self.push(value)?;
+                }
+                Opcode::GetBuiltin => {
+                    let index = self.read_u8(op)?;
+                    let builtin = self
+                        .builtins
+                        .get_by_index(index)
+                        .ok_or_else(|| format!("unknown builtin function #{}", index))?;
+                    self.push(builtin)?;
+                }
+                Opcode::GetFree => {
+                    let index = self.read_u8(op)?;
+                    let value = self
+                        .current_frame()
+                        .closure
+                        .free
+                        .get(index)
+                        .cloned()
+                        .ok_or_else(|| invalid_operand(op, index))?;
+                    self.push(value)?;
+                }
+                Opcode::CurrentClosure => {
+                    let closure = self.current_frame().closure.clone();
+                    self.push(Object::Closure(closure))?;
+                }
+                Opcode::Array => {
+                    let count = self.read_u16(op)?;
+                    let elements = self.pop_many(count)?;
+                    self.push(Object::Array(elements))?;
+                }
+                Opcode::Hash => {
+                    let count = self.read_u16(op)?;
+                    let elements = self.pop_many(count)?;
+                    self.push(build_hash(elements)?)?;
+                }
+                Opcode::Index => {
+                    let index = self.pop()?;
+                    let left = self.pop()?;
+                    self.push(eval_index_expression(left, index)?)?;
+                }
+                Opcode::Call => {
+                    let num_arguments = self.read_u8(op)?;
+                    self.call(num_arguments)?;
+                }
+                Opcode::ReturnValue => {
+                    let value = self.pop()?;
+                    if self.return_from_frame(value) {
+                        return Ok(());
+                    }
+                }
+                Opcode::Return => {
+                    if self.return_from_frame(Object::Null) {
+                        return Ok(());
+                    }
+                }
+                Opcode::Closure => {
+                    let index = self.read_u16(op)?;
+                    let num_free = self.read_u8(op)?;
+                    let function = match self.constant(op, index)? {
+                        Object::CompiledFunction(function) => function.clone(),
+                        constant => {
+                            return Err(VmErrorKind::Runtime(format!(
+                                "not a function: {}",
+                                constant.type_name()
+                            )))
+                        }
+                    };
+                    let free = self.pop_many(num_free)?;
+                    self.push(Object::Closure(Rc::new(Closure { function, free })))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, num_arguments: usize) -> Result<(), VmErrorKind> {
+        let callee_position = self
+            .stack
+            .len()
+            .checked_sub(num_arguments + 1)
+            .ok_or(VmErrorKind::StackUnderflow)?;
+
+        match &self.stack[callee_position] {
+            Object::Closure(closure) => {
+                let function = &closure.function;
+                if function.num_parameters != num_arguments {
+                    return Err(VmErrorKind::Runtime(format!(
+                        "wrong number of arguments: want={}, got={}",
+                        function.num_parameters, num_arguments
+                    )));
+                }
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+
+                let base_pointer = callee_position + 1;
+                let stack_size = base_pointer + function.num_locals;
+                if stack_size > STACK_SIZE {
+                    return Err(VmErrorKind::StackOverflow);
+                }
+                let frame = Frame {
+                    closure: closure.clone(),
+                    ip: 0,
+                    instruction_start: 0,
+                    base_pointer,
+                };
+                self.stack.resize(stack_size, Object::Null);
+                self.frames.push(frame);
+            }
+            Object::Builtin(builtin) => {
+                let builtin = builtin.clone();
+                let arguments = self.stack.split_off(callee_position + 1);
+                self.stack.pop();
+                let result = builtin
+                    .call(&arguments)
+                    .map_err(|error| VmErrorKind::Runtime(error.to_string()))?;
+                self.push(result)?;
+            }
+            callee => {
+                return Err(VmErrorKind::Runtime(format!(
+                    "not a function: {}",
+                    callee.type_name()
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    // 関数から戻る。最上位での return であればプログラムを終えるので true を返す
+    fn return_from_frame(&mut self, value: Object) -> bool {
+        let frame = self.frames.pop().expect("no frame to return from");
+        if self.frames.is_empty() {
+            self.last_popped = value;
+            return true;
+        }
+
+        // 呼び出された関数自身もスタックから取り除く
+        self.stack.truncate(frame.base_pointer - 1);
+        self.stack.push(value);
+        false
+    }
+
+    fn fetch(&mut self) -> Option<u8> {
+        let frame = self.frames.last_mut()?;
+        let byte = *frame.closure.function.instructions.0.get(frame.ip)?;
+        frame.instruction_start = frame.ip;
+        frame.ip += 1;
+        Some(byte)
+    }
+
+    fn read_u16(&mut self, op: Opcode) -> Result<usize, VmErrorKind> {
+        let frame = self.current_frame_mut();
+        let bytes = frame
+            .closure
+            .function
+            .instructions
+            .0
+            .get(frame.ip..frame.ip + 2)
+            .ok_or_else(|| VmErrorKind::TruncatedInstruction(op.definition().name))?;
+        frame.ip += 2;
+        Ok(read_u16(bytes) as usize)
+    }
+
+    fn read_u8(&mut self, op: Opcode) -> Result<usize, VmErrorKind> {
+        let frame = self.current_frame_mut();
+        let value = *frame
+            .closure
+            .function
+            .instructions
+            .0
+            .get(frame.ip)
+            .ok_or_else(|| VmErrorKind::TruncatedInstruction(op.definition().name))?;
+        frame.ip += 1;
+        Ok(value as usize)
+    }
+
+    fn constant(&self, op: Opcode, index: usize) -> Result<&Object, VmErrorKind> {
+        self.constants
+            .get(index)
+            .ok_or_else(|| invalid_operand(op, index))
+    }
+
+    // 実行中の命令の位置。ip は命令のオペコードより後を指している
+    fn current_span(&self) -> Option<Span> {
+        let frame = self.frames.last()?;
+        let offset = frame.ip.checked_sub(1)?;
+        frame.closure.function.source_map.find(offset)
+    }
+
+    fn current_function_index(&self) -> Option<usize> {
+        if self.frames.len() <= 1 {
+            return None;
+        }
+        let function = &self.current_frame().closure.function;
+        self.constants.iter().position(|constant| match constant {
+            Object::CompiledFunction(constant) => Rc::ptr_eq(constant, function),
+            _ => false,
+        })
+    }
+
+    fn current_frame(&self) -> &Frame {
+        self.frames.last().expect("no current frame")
+    }
+
+    fn current_frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("no current frame")
+    }
+
+    fn push(&mut self, object: Object) -> Result<(), VmErrorKind> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(VmErrorKind::StackOverflow);
+        }
+        self.stack.push(object);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Object, VmErrorKind> {
+        self.stack.pop().ok_or(VmErrorKind::StackUnderflow)
+    }
+
+    // スタックの上から count 個を取り出す
+    fn pop_many(&mut self, count: usize) -> Result<Vec<Object>, VmErrorKind> {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(count)
+            .ok_or(VmErrorKind::StackUnderflow)?;
+        Ok(self.stack.split_off(start))
+    }
+}
+
+fn invalid_operand(op: Opcode, operand: usize) -> VmErrorKind {
+    VmErrorKind::InvalidOperand {
+        opcode: op.definition().name,
+        operand,
+    }
+}
+
+fn infix_operator(op: Opcode) -> &'static str {
+    match op {
+        Opcode::Add => "+",
+        Opcode::Sub => "-",
+        Opcode::Mul => "*",
+        Opcode::Div => "/",
+        Opcode::Equal => "==",
+        Opcode::NotEqual => "!=",
+        Opcode::GreaterThan => ">",
+        Opcode::LessThan => "<",
+        _ => unreachable!("{:?} is not an infix operator", op),
+    }
+}
+
+fn build_hash(elements: Vec<Object>) -> Result<Object, VmErrorKind> {
+    let mut hash = HashMap::new();
+    let mut elements = elements.into_iter();
+
+    while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+        let hash_key = key
+            .hash_key()
+            .ok_or_else(|| format!("unusable as hash key: {}", key.type_name()))?;
+        hash.insert(hash_key, HashPair { key, value });
+    }
+
+    Ok(Object::Hash(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Vm, VmError, VmErrorKind};
+    use crate::{
+        code::{make, Instructions, Opcode, SourceMap},
+        compiler::{Bytecode, Compiler},
+        object::Object,
+        parse,
+        token::Span,
+    };
+
+    fn run_vm(input: &str) -> Result<Object, VmError> {
+        let program = parse(input).expect("failed to parse input");
+        let mut compiler = Compiler::new();
+        compiler.compile(&program).expect("failed to compile");
+
+        let mut vm = Vm::new(compiler.bytecode());
+        vm.run()?;
+        Ok(vm.last_popped_stack_elem().clone())
+    }
+
+    #[test]
+    fn test_calling_functions() {
+        let tests = vec![
+            ("let f = fn() { 5 + 10; }; f();", Object::Integer(15)),
+            ("let f = fn() { return 1; 2 }; f();", Object::Integer(1)),
+            ("let f = fn() { }; f();", Object::Null),
+            (
+                "let sum = fn(a, b) { let c = a + b; c; }; sum(1, 2) + sum(3, 4);",
+                Object::Integer(10),
+            ),
+            (
+                "let globalNum = 10; let f = fn(a) { let num = 1; globalNum + num + a }; f(2)",
+                Object::Integer(13),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), run_vm(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let tests = vec![
+            (
+                "let newAdder = fn(a, b) { fn(c) { a + b + c } }; newAdder(1, 2)(8);",
+                Object::Integer(11),
+            ),
+            (
+                "let a = fn(x) { fn(y) { fn(z) { x + y + z } } }; a(1)(2)(3)",
+                Object::Integer(6),
+            ),
+            (
+                "let wrapper = fn() {
+                    let countDown = fn(x) { if (x == 0) { return 0; } else { countDown(x - 1); } };
+                    countDown(5);
+                };
+                wrapper();",
+                Object::Integer(0),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), run_vm(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_top_level_statements() {
+        let tests = vec![
+            ("let x = 1;", Object::Null),
+            ("1; let x = 2;", Object::Null),
+            ("return 3; 4", Object::Integer(3)),
+            ("if (true) { return 5; } 6", Object::Integer(5)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(Ok(expected), run_vm(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let input = "let f = fn(x) { f(x + 1) }; f(0)";
+        assert_eq!(
+            Err(VmErrorKind::StackOverflow),
+            run_vm(input).map_err(|error| error.kind)
+        );
+    }
+
+    #[test]
+    fn test_error_span() {
+        // 評価器の test_error_span と同じ位置を指す
+        let tests = vec![
+            ("let x = 5;\nx + true;", 11, 19, 2, 1),
+            ("let f = fn(x) {\n  -x\n};\nf(true)", 18, 20, 2, 3),
+            ("let f = fn(x) { x };\nlen(1, f(2))", 21, 33, 2, 1),
+        ];
+
+        for (input, start, end, line, column) in tests {
+            let span = Span {
+                start,
+                end,
+                line,
+                column,
+            };
+            match run_vm(input) {
+                Err(error) => assert_eq!(Some(span), error.span, "{}", input),
+                result => panic!("expected error. got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_offset() {
+        // 関数の中であれば、その関数の定数プールでの添字と命令列の中での位置
+        let tests = vec![
+            ("let x = 5;\nx + true;", None, 10),
+            ("let f = fn(x) {\n  -x\n};\nf(true)", Some(0), 2),
+        ];
+
+        for (input, function, offset) in tests {
+            match run_vm(input) {
+                Err(error) => assert_eq!((function, offset), (error.function, error.offset)),
+                result => panic!("expected error. got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupted_bytecode() {
+        let tests = vec![
+            (vec![make(Opcode::Add, &[])], VmErrorKind::StackUnderflow),
+            (
+                vec![make(Opcode::Constant, &[5])],
+                VmErrorKind::InvalidOperand {
+                    opcode: "OpConstant",
+                    operand: 5,
+                },
+            ),
+            (
+                vec![vec![Opcode::Jump as u8, 0]],
+                VmErrorKind::TruncatedInstruction("OpJump"),
+            ),
+            (vec![make(Opcode::Array, &[3])], VmErrorKind::StackUnderflow),
+            (
+                vec![make(Opcode::GetLocal, &[0])],
+                VmErrorKind::InvalidOperand {
+                    opcode: "OpGetLocal",
+                    operand: 0,
+                },
+            ),
+            (vec![vec![255]], VmErrorKind::InvalidOpcode(255)),
+        ];
+
+        for (instructions, expected) in tests {
+            let bytecode = Bytecode {
+                instructions: Instructions(instructions.concat()),
+                constants: Rc::default(),
+                source_map: SourceMap::default(),
+            };
+            let error = Vm::new(bytecode).run().unwrap_err();
+            assert_eq!(expected, error.kind);
+            assert_eq!(None, error.span);
+            assert_eq!(0, error.offset);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fs, path::PathBuf, process::Command};
+
+fn write_script(name: &str, source: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let path = dir.join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn test_run_script_with_args() {
+    let path = write_script(
+        "args.mk",
+        r#"let a = args();
+puts(len(a), first(a) + "!");
+"#,
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&path)
+        .args(["hello", "world"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!("2\nhello!\n", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn test_run_script_on_vm() {
+    let path = write_script(
+        "fibonacci.mk",
+        "let fibonacci = fn(x) {
+  if (x < 2) { return x; }
+  fibonacci(x - 1) + fibonacci(x - 2)
+};
+puts(fibonacci(15), args());
+",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .args(["--engine", "vm"])
+        .arg(&path)
+        .arg("x")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!("610\n[x]\n", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn test_vm_runtime_error_exits_non_zero() {
+    let path = write_script("vm_error.mk", "let x = 1;\nx + true;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("--engine=vm")
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        format!(
+            "error: type mismatch: INTEGER + BOOLEAN\n --> {}:2:1\n  |\n2 | x + true;\n  | ^^^^^^^^\n",
+            path.display()
+        ),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_disassemble_script() {
+    let path = write_script("disasm.mk", "puts(1 + 2);\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("disasm")
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        "\
+== main ==
+0000 OpGetBuiltin 5
+0002 OpConstant 0
+0005 OpConstant 1
+0008 OpAdd
+0009 OpCall 1
+0011 OpPop
+
+== constant 0: 1 ==
+
+== constant 1: 2 ==
+",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_compile_and_run_mkc() {
+    let path = write_script(
+        "compiled.mk",
+        r#"let greet = fn(name) { "hello, " + name };
+puts(greet(first(args())));
+"#,
+    );
+    let compiled = path.with_extension("mkc");
+    let _ = fs::remove_file(&compiled);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("compile")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(fs::read(&compiled).unwrap().starts_with(b"MKC\0"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&compiled)
+        .arg("monkey")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!("hello, monkey\n", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn test_mkc_runtime_error_shows_offset() {
+    let path = write_script("mkc_error.mk", "let f = fn(x) { -x };\nf(true);\n");
+    let compiled = path.with_extension("mkc");
+    let status = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("compile")
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&compiled)
+        .output()
+        .unwrap();
+
+    // 関数 (定数 0) の 2 バイト目にある OpMinus で失敗する
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        format!(
+            "error: unknown operator: -BOOLEAN\n --> {}: in constant 0 at 2\n",
+            compiled.display()
+        ),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_corrupted_mkc_exits_non_zero() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("corrupted.mkc");
+    fs::write(&path, b"MKC\0\x00\x01\x00").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unexpected end of file"), "{}", stderr);
+}
+
+#[test]
+fn test_corrupted_bytecode_exits_non_zero() {
+    let source = write_script("corrupted_bytecode.mk", "1 + 2;\n");
+    let compiled = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("corrupted_bytecode.mkc");
+    let status = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("compile")
+        .arg(&source)
+        .arg(&compiled)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let bytes = fs::read(&compiled).unwrap();
+
+    // OpConstant 0, OpConstant 1, OpAdd, OpPop
+    let instructions: &[u8] = &[0, 0, 0, 0, 0, 1, 2, 1];
+    let position = bytes
+        .windows(instructions.len())
+        .position(|window| window == instructions)
+        .unwrap();
+
+    // ヘッダは正しいまま、存在しない定数を参照させる、または空のスタックから取り出させる
+    let tests: Vec<(&[u8], &str)> = vec![
+        (
+            &[0, 0, 5, 0, 0, 1, 2, 1],
+            "invalid bytecode in main at 0: invalid operand 5 of OpConstant",
+        ),
+        (&[2, 2, 2, 2, 2, 2, 2, 1], "error: stack underflow\n --> "),
+        (&[0, 0, 0, 2, 2, 2, 2, 1], ": in main at 3\n"),
+    ];
+    for (replacement, expected) in tests {
+        let mut corrupted = bytes.clone();
+        corrupted[position..position + replacement.len()].copy_from_slice(replacement);
+        fs::write(&compiled, corrupted).unwrap();
+
+        let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+            .arg(&compiled)
+            .output()
+            .unwrap();
+
+        assert_eq!(Some(1), output.status.code());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_unknown_engine() {
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("--engine=jit")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(2), output.status.code());
+}
+
+#[test]
+fn test_mode_with_script() {
+    let path = write_script("mode.mk", "1;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("--mode=ast")
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(2), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--mode only applies to the REPL"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_runtime_error_exits_non_zero() {
+    let path = write_script("runtime_error.mk", "let x = 1;\nx + true;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with("error: type mismatch: INTEGER + BOOLEAN\n"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("runtime_error.mk:2:1"), "{}", stderr);
+}
+
+#[test]
+fn test_parse_error_exits_non_zero() {
+    let path = write_script("parse_error.mk", "let x 1;\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("parse_error.mk:1:7"), "{}", stderr);
+}
+
+#[test]
+fn test_missing_file_exits_non_zero() {
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg("does-not-exist.mk")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+}